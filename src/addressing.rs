@@ -0,0 +1,232 @@
+/// Addressing modes of the 6502 instructions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Relative,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    /// `(zp)`, added by the 65C02
+    ZeroPageIndirect,
+    /// `(abs,X)`, only used by JMP on the 65C02
+    AbsoluteIndirectX,
+    /// A zero page address then a branch offset, used by BBR and BBS
+    ZeroPageRelative,
+    // Added by the 65C816, whose zero page is the direct page
+    /// 16-bit branch offset, BRL and PER
+    RelativeLong,
+    /// `[dp]`, 24-bit pointer in the direct page
+    ZeroPageIndirectLong,
+    /// `[dp],Y`
+    ZeroPageIndirectLongY,
+    /// 24-bit address
+    AbsoluteLong,
+    AbsoluteLongX,
+    /// `[abs]`, only used by JML
+    AbsoluteIndirectLong,
+    /// `sr,S`, offset from the stack pointer
+    StackRelative,
+    /// `(sr,S),Y`
+    StackRelativeIndirectY,
+    /// Destination bank then source bank, MVN and MVP
+    BlockMove,
+}
+
+/// What an addressing mode resolved to for the current instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Operand {
+    /// Effective address, `None` for implied and accumulator addressing.
+    /// For relative and zero page relative addressing this is the branch target.
+    pub address: Option<u16>,
+    /// Whether indexing (or the branch) went to another page than the base address
+    pub page_crossed: bool,
+}
+
+impl AddressingMode {
+    /// Number of bytes taken by an instruction using this addressing mode, opcode included.
+    /// 16-bit immediate operands of the 65C816 take one more.
+    pub fn length(self) -> u16 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 1,
+            AddressingMode::Immediate | AddressingMode::ZeroPage | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY | AddressingMode::Relative
+            | AddressingMode::IndirectX | AddressingMode::IndirectY
+            | AddressingMode::ZeroPageIndirect | AddressingMode::ZeroPageIndirectLong
+            | AddressingMode::ZeroPageIndirectLongY | AddressingMode::StackRelative
+            | AddressingMode::StackRelativeIndirectY => 2,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect | AddressingMode::AbsoluteIndirectX
+            | AddressingMode::ZeroPageRelative | AddressingMode::RelativeLong
+            | AddressingMode::AbsoluteIndirectLong | AddressingMode::BlockMove => 3,
+            AddressingMode::AbsoluteLong | AddressingMode::AbsoluteLongX => 4,
+        }
+    }
+}
+
+/// Resolve the operand of the instruction at `pc`.
+///
+/// `read` is used to fetch the operand bytes and any pointer they refer to.
+/// Zero page indexing and zero page pointers wrap around within page zero.
+/// On the NMOS chips (`cmos` false) JMP ($xxFF) takes its high byte from $xx00.
+pub fn resolve<F: FnMut(u16) -> u8>(mode: AddressingMode, pc: u16, x: u8, y: u8, cmos: bool, mut read: F) -> Operand {
+    let arg = pc.wrapping_add(1);
+    let (address, page_crossed) = match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator => {
+            return Operand { address: None, page_crossed: false };
+        },
+        AddressingMode::Immediate => (arg, false),
+        AddressingMode::ZeroPage => (read(arg) as u16, false),
+        AddressingMode::ZeroPageX => (read(arg).wrapping_add(x) as u16, false),
+        AddressingMode::ZeroPageY => (read(arg).wrapping_add(y) as u16, false),
+        AddressingMode::Relative => {
+            let offset = read(arg) as i8;
+            let next = pc.wrapping_add(2);
+            let target = next.wrapping_add(offset as u16);
+            (target, !same_page(next, target))
+        },
+        AddressingMode::Absolute => (word(arg, &mut read), false),
+        AddressingMode::AbsoluteX => indexed(word(arg, &mut read), x),
+        AddressingMode::AbsoluteY => indexed(word(arg, &mut read), y),
+        AddressingMode::Indirect => {
+            let pointer = word(arg, &mut read);
+            if cmos {
+                (word(pointer, &mut read), false)
+            } else {
+                (page_word(pointer, &mut read), false)
+            }
+        },
+        AddressingMode::IndirectX => {
+            let pointer = read(arg).wrapping_add(x);
+            (page_word(pointer as u16, &mut read), false)
+        },
+        AddressingMode::IndirectY => {
+            let pointer = read(arg);
+            indexed(page_word(pointer as u16, &mut read), y)
+        },
+        AddressingMode::ZeroPageIndirect => {
+            let pointer = read(arg);
+            (page_word(pointer as u16, &mut read), false)
+        },
+        AddressingMode::AbsoluteIndirectX => {
+            let pointer = word(arg, &mut read).wrapping_add(x as u16);
+            (word(pointer, &mut read), false)
+        },
+        AddressingMode::ZeroPageRelative => {
+            let offset = read(pc.wrapping_add(2)) as i8;
+            let next = pc.wrapping_add(3);
+            let target = next.wrapping_add(offset as u16);
+            (target, !same_page(next, target))
+        },
+        _ => unreachable!("{:?} is only used by the 65C816 core", mode),
+    };
+
+    Operand { address: Some(address), page_crossed }
+}
+
+/// Little endian word at `address`
+fn word<F: FnMut(u16) -> u8>(address: u16, read: &mut F) -> u16 {
+    let low_byte = read(address) as u16;
+    let high_byte = read(address.wrapping_add(1)) as u16;
+    low_byte + (high_byte << 8)
+}
+
+/// Little endian word at `address`, whose high byte is read from the same
+/// page: only the low byte of the address is incremented
+fn page_word<F: FnMut(u16) -> u8>(address: u16, read: &mut F) -> u16 {
+    let low_byte = read(address) as u16;
+    let high_byte = read(address & 0xff00 | address.wrapping_add(1) & 0x00ff) as u16;
+    low_byte + (high_byte << 8)
+}
+
+fn indexed(base: u16, index: u8) -> (u16, bool) {
+    let address = base.wrapping_add(index as u16);
+    (address, !same_page(base, address))
+}
+
+fn same_page(a: u16, b: u16) -> bool {
+    a & 0xff00 == b & 0xff00
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolve the operand of the instruction at $0200 in `memory`
+    fn resolve_in(memory: &[u8], mode: AddressingMode, x: u8, y: u8, cmos: bool) -> Operand {
+        resolve(mode, 0x0200, x, y, cmos, |address| memory[address as usize])
+    }
+
+    fn memory_with(bytes: &[(u16, u8)]) -> Vec<u8> {
+        let mut memory = vec![0; 0x10000];
+        for &(address, value) in bytes {
+            memory[address as usize] = value;
+        }
+        memory
+    }
+
+    #[test]
+    fn nmos_jmp_indirect_wraps_within_the_page() {
+        let memory = memory_with(&[(0x0201, 0xff), (0x0202, 0x10), (0x10ff, 0x34), (0x1000, 0x12), (0x1100, 0x56)]);
+        let operand = resolve_in(&memory, AddressingMode::Indirect, 0, 0, false);
+        assert_eq!(operand.address, Some(0x1234));
+    }
+
+    #[test]
+    fn cmos_jmp_indirect_reads_the_next_page() {
+        let memory = memory_with(&[(0x0201, 0xff), (0x0202, 0x10), (0x10ff, 0x34), (0x1000, 0x12), (0x1100, 0x56)]);
+        let operand = resolve_in(&memory, AddressingMode::Indirect, 0, 0, true);
+        assert_eq!(operand.address, Some(0x5634));
+    }
+
+    #[test]
+    fn jmp_indirect_pointer_at_ffff() {
+        let memory = memory_with(&[(0x0201, 0xff), (0x0202, 0xff), (0xffff, 0x34), (0xff00, 0x12), (0x0000, 0x56)]);
+        assert_eq!(resolve_in(&memory, AddressingMode::Indirect, 0, 0, false).address, Some(0x1234));
+        assert_eq!(resolve_in(&memory, AddressingMode::Indirect, 0, 0, true).address, Some(0x5634));
+    }
+
+    #[test]
+    fn zero_page_indexing_wraps() {
+        let memory = memory_with(&[(0x0201, 0xf0)]);
+        assert_eq!(resolve_in(&memory, AddressingMode::ZeroPageX, 0x20, 0, false).address, Some(0x0010));
+        assert_eq!(resolve_in(&memory, AddressingMode::ZeroPageY, 0, 0x10, false).address, Some(0x0000));
+    }
+
+    #[test]
+    fn indirect_x_pointer_wraps_in_page_zero() {
+        // The pointer is at $FF/$00, not $FF/$100
+        let memory = memory_with(&[(0x0201, 0xf0), (0x00ff, 0x34), (0x0000, 0x12), (0x0100, 0x56)]);
+        assert_eq!(resolve_in(&memory, AddressingMode::IndirectX, 0x0f, 0, false).address, Some(0x1234));
+        // X is added to the zero page address without a carry
+        let memory = memory_with(&[(0x0201, 0xf0), (0x0010, 0x78), (0x0011, 0x56)]);
+        assert_eq!(resolve_in(&memory, AddressingMode::IndirectX, 0x20, 0, false).address, Some(0x5678));
+    }
+
+    #[test]
+    fn indirect_y_pointer_wraps_in_page_zero() {
+        let memory = memory_with(&[(0x0201, 0xff), (0x00ff, 0xf0), (0x0000, 0x12), (0x0100, 0x56)]);
+        let operand = resolve_in(&memory, AddressingMode::IndirectY, 0, 0x20, false);
+        assert_eq!(operand, Operand { address: Some(0x1310), page_crossed: true });
+    }
+
+    #[test]
+    fn zero_page_indirect_pointer_wraps_in_page_zero() {
+        let memory = memory_with(&[(0x0201, 0xff), (0x00ff, 0x34), (0x0000, 0x12), (0x0100, 0x56)]);
+        assert_eq!(resolve_in(&memory, AddressingMode::ZeroPageIndirect, 0, 0, true).address, Some(0x1234));
+    }
+
+    #[test]
+    fn absolute_indexing_wraps_at_ffff() {
+        let memory = memory_with(&[(0x0201, 0xf0), (0x0202, 0xff)]);
+        let operand = resolve_in(&memory, AddressingMode::AbsoluteX, 0x20, 0, false);
+        assert_eq!(operand, Operand { address: Some(0x0010), page_crossed: true });
+    }
+}
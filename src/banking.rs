@@ -0,0 +1,292 @@
+//! Bank switching: windows of the address space showing a bank of a ROM
+//! larger than it, selected by writing to a register.
+
+use std::fmt::Debug;
+
+use crate::bus::Bus;
+
+/// Size of a switchable bank
+pub const BANK_SIZE: usize = 0x4000;
+
+/// A bank switching scheme
+pub trait Mapper: Debug {
+    fn name(&self) -> &'static str;
+
+    /// Offset in the ROM of the byte a window shows at `address`, if a window covers it
+    fn map(&self, address: u16) -> Option<usize>;
+
+    /// See a write of the processor, which may switch banks. Returns whether
+    /// it went to the mapper instead of the memory below.
+    fn write(&mut self, address: u16, value: u8) -> bool;
+
+    /// Start address, end address and bank of each window
+    fn windows(&self) -> Vec<(u16, u16, usize)>;
+}
+
+/// Parse the name given to `--mapper`: `nrom`, `uxrom`, or `window:<register>`
+/// for a 16KiB window at $8000 switched by writes to `register`
+pub fn mapper(name: &str, rom_size: usize) -> Result<Box<dyn Mapper + Send>, String> {
+    let banks = rom_size / BANK_SIZE;
+    if banks == 0 || !rom_size.is_multiple_of(BANK_SIZE) {
+        return Err(format!("The ROM must be a multiple of 16KiB, it has {} bytes", rom_size));
+    }
+    let mut parts = name.splitn(2, ':');
+    match (parts.next().unwrap().to_lowercase().as_str(), parts.next()) {
+        ("nrom", None) => {
+            if banks > 2 {
+                return Err(format!("NROM has 16 or 32KiB of ROM, not {}KiB", rom_size / 1024));
+            }
+            Ok(Box::new(Nrom { banks }))
+        },
+        ("uxrom", None) => Ok(Box::new(UxRom { banks, bank: 0 })),
+        ("window", Some(register)) => {
+            let register = crate::options::parse_number(register)?;
+            Ok(Box::new(SwitchableWindow { banks, bank: 0, register }))
+        },
+        _ => Err(format!("Unknown mapper {}", name)),
+    }
+}
+
+/// The ROM of an iNES cartridge dump, or the whole file when it is a plain ROM
+pub fn prg_rom(image: &[u8]) -> Result<&[u8], String> {
+    if !image.starts_with(b"NES\x1a") {
+        return Ok(image);
+    }
+    if image.len() < 16 {
+        return Err("Truncated iNES header".to_string());
+    }
+    // A 512 byte trainer may come between the header and the ROM
+    let start = if image[6] & 0x04 != 0 { 16 + 512 } else { 16 };
+    let end = start + image[4] as usize * BANK_SIZE;
+    image.get(start..end).ok_or_else(|| "The iNES file is shorter than its header says".to_string())
+}
+
+/// No switching: 32KiB at $8000, or 16KiB there and mirrored at $C000
+#[derive(Debug)]
+pub struct Nrom {
+    banks: usize,
+}
+
+impl Mapper for Nrom {
+    fn name(&self) -> &'static str {
+        "NROM"
+    }
+
+    fn map(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            return None;
+        }
+        Some((address as usize - 0x8000) % (self.banks * BANK_SIZE))
+    }
+
+    fn write(&mut self, address: u16, _value: u8) -> bool {
+        address >= 0x8000
+    }
+
+    fn windows(&self) -> Vec<(u16, u16, usize)> {
+        vec![(0x8000, 0xbfff, 0), (0xc000, 0xffff, self.banks - 1)]
+    }
+}
+
+/// A switchable bank at $8000 and the last bank fixed at $C000. Writes to
+/// the ROM latch the number of the bank.
+#[derive(Debug)]
+pub struct UxRom {
+    banks: usize,
+    bank: usize,
+}
+
+impl Mapper for UxRom {
+    fn name(&self) -> &'static str {
+        "UxROM"
+    }
+
+    fn map(&self, address: u16) -> Option<usize> {
+        window_offset(address, self.bank, self.banks)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> bool {
+        if address < 0x8000 {
+            return false;
+        }
+        self.bank = value as usize % self.banks;
+        true
+    }
+
+    fn windows(&self) -> Vec<(u16, u16, usize)> {
+        vec![(0x8000, 0xbfff, self.bank), (0xc000, 0xffff, self.banks - 1)]
+    }
+}
+
+/// A switchable bank at $8000 and the last bank fixed at $C000, like UxROM,
+/// with the bank selected by a register anywhere in the address space.
+/// The register write also reaches the memory below.
+#[derive(Debug)]
+pub struct SwitchableWindow {
+    banks: usize,
+    bank: usize,
+    register: u16,
+}
+
+impl Mapper for SwitchableWindow {
+    fn name(&self) -> &'static str {
+        "16KiB window"
+    }
+
+    fn map(&self, address: u16) -> Option<usize> {
+        window_offset(address, self.bank, self.banks)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> bool {
+        if address == self.register {
+            self.bank = value as usize % self.banks;
+        }
+        address >= 0x8000
+    }
+
+    fn windows(&self) -> Vec<(u16, u16, usize)> {
+        vec![(0x8000, 0xbfff, self.bank), (0xc000, 0xffff, self.banks - 1)]
+    }
+}
+
+/// Offset of `address` with `bank` at $8000 and the last bank at $C000
+fn window_offset(address: u16, bank: usize, banks: usize) -> Option<usize> {
+    match address {
+        0x8000..=0xbfff => Some(bank * BANK_SIZE + (address as usize - 0x8000)),
+        0xc000..=0xffff => Some((banks - 1) * BANK_SIZE + (address as usize - 0xc000)),
+        _ => None,
+    }
+}
+
+/// A bus with a banked ROM over part of the address space, the rest goes
+/// to the bus below
+#[derive(Debug)]
+pub struct BankedBus<B: Bus> {
+    inner: B,
+    rom: Vec<u8>,
+    mapper: Box<dyn Mapper + Send>,
+}
+
+impl<B: Bus> BankedBus<B> {
+    pub fn new(inner: B, rom: Vec<u8>, mapper: Box<dyn Mapper + Send>) -> BankedBus<B> {
+        BankedBus { inner, rom, mapper }
+    }
+}
+
+impl<B: Bus> Bus for BankedBus<B> {
+    fn read(&mut self, address: u32) -> u8 {
+        if address > 0xffff {
+            return self.inner.read(address);
+        }
+        match self.mapper.map(address as u16) {
+            Some(offset) => self.rom[offset],
+            None => self.inner.read(address),
+        }
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        if address > 0xffff || !self.mapper.write(address as u16, value) {
+            self.inner.write(address, value);
+        }
+    }
+
+    fn peek(&self, address: u32) -> u8 {
+        if address > 0xffff {
+            return self.inner.peek(address);
+        }
+        match self.mapper.map(address as u16) {
+            Some(offset) => self.rom[offset],
+            None => self.inner.peek(address),
+        }
+    }
+
+    fn take_fault(&mut self) -> Option<String> {
+        self.inner.take_fault()
+    }
+
+    fn banks(&self) -> Vec<String> {
+        let mut lines = vec![self.mapper.name().to_string()];
+        for (start, end, bank) in self.mapper.windows() {
+            lines.push(format!("${:04X}-${:04X} bank {}", start, end, bank));
+        }
+        lines
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    fn irq(&self) -> bool {
+        self.inner.irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.inner.nmi()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Ram;
+
+    /// 4 banks, each one filled with its number
+    fn rom() -> Vec<u8> {
+        (0..4).flat_map(|bank| vec![bank as u8; BANK_SIZE]).collect()
+    }
+
+    fn bus(name: &str) -> BankedBus<Ram> {
+        let rom = rom();
+        let mapper = mapper(name, rom.len()).unwrap();
+        BankedBus::new(Ram::new(vec![]), rom, mapper)
+    }
+
+    #[test]
+    fn uxrom_latches_the_bank() {
+        let mut bus = bus("uxrom");
+        assert_eq!((bus.peek(0x8000), bus.peek(0xffff)), (0, 3));
+        bus.write(0x8123, 2);
+        assert_eq!((bus.peek(0xbfff), bus.peek(0xc000)), (2, 3));
+        assert_eq!(bus.banks(), vec!["UxROM", "$8000-$BFFF bank 2", "$C000-$FFFF bank 3"]);
+        // RAM below is still there
+        bus.write(0x0200, 0x42);
+        assert_eq!(bus.peek(0x0200), 0x42);
+    }
+
+    #[test]
+    fn window_register() {
+        let mut bus = bus("window:$6000");
+        bus.write(0x8000, 1);
+        assert_eq!(bus.peek(0x8000), 0);
+        bus.write(0x6000, 1);
+        assert_eq!(bus.peek(0x8000), 1);
+        // The register is also RAM
+        assert_eq!(bus.peek(0x6000), 1);
+    }
+
+    #[test]
+    fn nrom_mirrors_16k() {
+        let mut rom = vec![0; BANK_SIZE];
+        rom[0] = 0x42;
+        let mapper = mapper("nrom", rom.len()).unwrap();
+        let mut bus = BankedBus::new(Ram::new(vec![]), rom, mapper);
+        bus.write(0xc000, 0);
+        assert_eq!((bus.peek(0x8000), bus.peek(0xc000)), (0x42, 0x42));
+        assert!(super::mapper("nrom", 4 * BANK_SIZE).is_err());
+    }
+
+    #[test]
+    fn ines_header() {
+        let mut image = b"NES\x1a\x02\x00\x00\x00".to_vec();
+        image.resize(16, 0);
+        image.extend(rom()[..2 * BANK_SIZE].iter());
+        image.extend(vec![0xff; 0x2000]);
+        let prg = prg_rom(&image).unwrap();
+        assert_eq!(prg.len(), 2 * BANK_SIZE);
+        assert_eq!(prg[BANK_SIZE], 1);
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Debug;
+
+use crate::computer::MEMORY_SIZE;
+
+/// What the processor is connected to. The computer runs all its memory
+/// accesses through a `Bus`, so memory mapped hardware can be modelled by
+/// implementing this trait.
+///
+/// Addresses are 24 bits wide for the 65C816, the other processors only use
+/// the low 16 bits.
+pub trait Bus: Debug {
+    /// A read by the processor, which a device may react to
+    fn read(&mut self, address: u32) -> u8;
+
+    /// A write by the processor
+    fn write(&mut self, address: u32, value: u8);
+
+    /// The byte a read would return, without its side effects. Used by the
+    /// UI and the debugger.
+    fn peek(&self, address: u32) -> u8;
+
+    /// A problem with the last write to show in the debug info, like a
+    /// write to ROM
+    fn take_fault(&mut self) -> Option<String> {
+        None
+    }
+
+    /// The banks switched in and where, for the UI
+    fn banks(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Let the devices on the bus run for `cycles` clock cycles
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// The reset line was asserted
+    fn reset(&mut self) {}
+
+    /// Whether a device on the bus asserts IRQ
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether a device on the bus asserts NMI
+    fn nmi(&self) -> bool {
+        false
+    }
+}
+
+/// So the bus can be picked at run time
+impl<T: Bus + ?Sized> Bus for Box<T> {
+    fn read(&mut self, address: u32) -> u8 {
+        (**self).read(address)
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        (**self).write(address, value)
+    }
+
+    fn peek(&self, address: u32) -> u8 {
+        (**self).peek(address)
+    }
+
+    fn take_fault(&mut self) -> Option<String> {
+        (**self).take_fault()
+    }
+
+    fn banks(&self) -> Vec<String> {
+        (**self).banks()
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        (**self).tick(cycles)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn irq(&self) -> bool {
+        (**self).irq()
+    }
+
+    fn nmi(&self) -> bool {
+        (**self).nmi()
+    }
+}
+
+/// Plain RAM, the default bus
+#[derive(Clone, Debug)]
+pub struct Ram {
+    data: Vec<u8>,
+}
+
+impl Ram {
+    /// RAM initialised with `data`, anything short of 64KiB is filled with zeroes
+    pub fn new(mut data: Vec<u8>) -> Ram {
+        if data.len() < MEMORY_SIZE {
+            data.resize(MEMORY_SIZE, 0);
+        }
+        Ram { data }
+    }
+}
+
+impl Bus for Ram {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+
+    /// The banks above 64KiB only used by the 65C816 are added when first written
+    fn write(&mut self, address: u32, value: u8) {
+        let address = address as usize;
+        if address >= self.data.len() {
+            self.data.resize((address | 0xffff) + 1, 0);
+        }
+        self.data[address] = value;
+    }
+
+    /// Banks never written read as zeroes
+    fn peek(&self, address: u32) -> u8 {
+        self.data.get(address as usize).copied().unwrap_or(0)
+    }
+}
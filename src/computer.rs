@@ -2,6 +2,34 @@ use std::sync::mpsc;
 use std::time;
 use std::thread;
 
+use crate::addressing::{self, AddressingMode as Mode, Operand};
+use crate::bus::{Bus, Ram};
+use crate::cycles::INTERRUPT_CYCLES;
+use crate::instructions::{Access, Instruction};
+use crate::interrupts::{InterruptLines, IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+use crate::symbols::Symbols;
+use crate::variant::{Cpu, Variant};
+
+mod cycle;
+mod native;
+#[cfg(test)]
+mod tests;
+
+use cycle::CycleState;
+
+/// Number of debug messages kept in `Processor.info`
+const MAX_INFO: usize = 100;
+
+/// The 6502 always sees a full 64KiB address space
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// The hardware stack is page $01
+const STACK_PAGE: u16 = 0x100;
+
+/// Bits ORed into the accumulator by the unstable ANE and LXA. Varies between
+/// chips and with temperature, $EE is what most of them do.
+const UNSTABLE_MAGIC: u8 = 0xee;
+
 #[derive(Clone, Debug)]
 pub struct Info {
     pub msg: String,
@@ -12,7 +40,17 @@ pub enum ControllerMessage {
     ButtonPressed(String),
     GetData(),
     UpdatedProcessorAvailable(Processor),
-    UpdatedDataAvailable(Vec<u8>),
+    /// Address of the first byte and the memory around PC
+    UpdatedDataAvailable(u16, Vec<u8>),
+    /// Stack pointer and the bytes on the stack above it
+    UpdatedStackAvailable(u8, Vec<u8>),
+    /// The banks switched in, a line per window
+    UpdatedBanksAvailable(Vec<String>),
+    /// Drive the IRQ line, true to assert it
+    SetIrq(bool),
+    /// Drive the NMI line, an NMI happens when it goes from released to asserted
+    SetNmi(bool),
+    Reset(),
 }
 
 #[derive(Clone, Debug)]
@@ -26,44 +64,243 @@ pub struct Processor {
     pub test: u8,
     pub info: Vec<Info>,
     pub clock: u64,
+    /// Cycle of the instruction being run by the cycle-stepped core,
+    /// 0 between instructions
+    pub cycle: u8,
+    /// The last bus access
+    pub bus: Option<BusCycle>,
+    /// Stopped by a JAM or STP instruction, only a reset gets it going again
+    pub halted: bool,
+    /// Waiting for an interrupt after a WAI instruction
+    pub waiting: bool,
+    // The rest is only used by the 65C816
+    /// High byte of the accumulator, B in 8-bit mode
+    pub acc_high: u8,
+    /// High bytes of the index registers, 0 while they are 8-bit
+    pub rx_high: u8,
+    pub ry_high: u8,
+    /// High byte of the stack pointer, $01 in emulation mode
+    pub sp_high: u8,
+    /// Direct page register
+    pub dp: u16,
+    /// Data bank register
+    pub dbr: u8,
+    /// Program bank register
+    pub pbr: u8,
+    /// Running in 6502 emulation mode, always true for the 8-bit processors
+    pub emulation: bool,
+}
+
+/// One access of the processor to the bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusCycle {
+    /// 24 bits wide on the 65C816
+    pub address: u32,
+    pub value: u8,
+    pub write: bool,
+}
+
+// Bits of the processor status register
+pub const CARRY: u8 = 0b00000001;
+pub const ZERO: u8 = 0b00000010;
+pub const INTERRUPT: u8 = 0b00000100;
+pub const DECIMAL: u8 = 0b00001000;
+/// Only exists in the copy of the status pushed on the stack, set by PHP and BRK
+pub const BREAK: u8 = 0b00010000;
+/// Not connected, always reads as 1
+pub const UNUSED: u8 = 0b00100000;
+pub const OVERFLOW: u8 = 0b01000000;
+pub const NEGATIVE: u8 = 0b10000000;
+
+impl Processor {
+    pub fn carry(&self) -> bool {
+        self.flags & CARRY != 0
+    }
+
+    pub fn zero(&self) -> bool {
+        self.flags & ZERO != 0
+    }
+
+    pub fn interrupt(&self) -> bool {
+        self.flags & INTERRUPT != 0
+    }
+
+    pub fn decimal(&self) -> bool {
+        self.flags & DECIMAL != 0
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.flags & OVERFLOW != 0
+    }
+
+    pub fn negative(&self) -> bool {
+        self.flags & NEGATIVE != 0
+    }
+
+    pub fn set_carry(&mut self, value: bool) {
+        self.set_flag(CARRY, value);
+    }
+
+    pub fn set_zero(&mut self, value: bool) {
+        self.set_flag(ZERO, value);
+    }
+
+    pub fn set_interrupt(&mut self, value: bool) {
+        self.set_flag(INTERRUPT, value);
+    }
+
+    pub fn set_decimal(&mut self, value: bool) {
+        self.set_flag(DECIMAL, value);
+    }
+
+    pub fn set_overflow(&mut self, value: bool) {
+        self.set_flag(OVERFLOW, value);
+    }
+
+    pub fn set_negative(&mut self, value: bool) {
+        self.set_flag(NEGATIVE, value);
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    /// Set the zero and negative flags according to `val`
+    pub fn set_zn(&mut self, val: u8) {
+        self.set_zero(val == 0);
+        self.set_negative(val & 0x80 != 0);
+    }
+
+    /// Status register as pushed on the stack. The B bit is only set when
+    /// pushed by PHP or BRK, not when an IRQ or NMI is serviced.
+    pub fn pushed_status(&self, break_flag: bool) -> u8 {
+        let status = self.flags | UNUSED;
+        if break_flag {
+            status | BREAK
+        } else {
+            status & !BREAK
+        }
+    }
+
+    /// Load the status register from a value pulled by PLP or RTI.
+    /// B and the unused bit do not exist in the register itself.
+    pub fn set_pulled_status(&mut self, status: u8) {
+        self.flags = (status & !BREAK) | UNUSED;
+    }
+
+    /// Flags as letters, uppercase when set: NV-BDIZC, or NVMXDIZC in the
+    /// native mode of the 65C816
+    pub fn flags_string(&self) -> String {
+        let names = if self.emulation { "NV-BDIZC" } else { "NVMXDIZC" };
+        names.chars().enumerate().map(|(i, c)| {
+            if self.flags & (0x80 >> i) != 0 { c } else { c.to_ascii_lowercase() }
+        }).collect()
+    }
 }
 
+/// The processor and what it is connected to, plain RAM unless another bus is given
 #[derive(Debug)]
-pub struct Computer {
+pub struct Computer<B: Bus = Ram> {
     processor: Processor,
     paused: bool,
     step: bool,
     speed: u64,
-    data: Vec<u8>,
+    bus: B,
+    interrupts: InterruptLines,
+    /// Whether IRQs are masked when the interrupt lines are polled, which
+    /// happens before CLI, SEI and PLP change the I flag
+    irq_masked: bool,
+    /// Writes to this address drive the IRQ (bit 0) and NMI (bit 1) lines
+    interrupt_port: Option<u16>,
+    cpu: Box<dyn Cpu>,
+    /// Run a clock cycle at a time instead of an instruction at a time
+    cycle_stepped: bool,
+    cycle: CycleState,
+    /// Names of addresses for the debug info
+    symbols: Symbols,
+    /// Running pauses when PC gets to one of these
+    breakpoints: Vec<u16>,
     tx: mpsc::Sender<ControllerMessage>,
     rx: mpsc::Receiver<ControllerMessage>,
 }
 
+/// Copy `image` into the 64KiB memory `data` at `address`
+pub fn place_image(data: &mut [u8], image: &[u8], address: u16) -> Result<(), String> {
+    let start = address as usize;
+    if start + image.len() > MEMORY_SIZE {
+        return Err(format!("An image of {} bytes does not fit at {:#06x}", image.len(), address));
+    }
+    data[start..start + image.len()].copy_from_slice(image);
+    Ok(())
+}
+
 impl Computer {
+    /// A computer with RAM holding `data`, anything short of 64KiB is filled with zeroes
     pub fn new(tx: mpsc::Sender<ControllerMessage>, rx:  mpsc::Receiver<ControllerMessage>, data: Vec<u8>) -> Computer {
+        Computer::with_bus(tx, rx, Ram::new(data))
+    }
+}
+
+impl<B: Bus> Computer<B> {
+    /// A computer with the processor connected to `bus`
+    pub fn with_bus(tx: mpsc::Sender<ControllerMessage>, rx:  mpsc::Receiver<ControllerMessage>, bus: B) -> Computer<B> {
         let mut computer = Computer {
-            data,
+            bus,
+            interrupts: InterruptLines::default(),
+            irq_masked: false,
+            interrupt_port: None,
+            cpu: Variant::default().cpu(),
+            cycle_stepped: false,
+            cycle: CycleState::new(),
+            symbols: Symbols::default(),
+            breakpoints: vec![],
             tx,
             rx,
             paused: true,
             step: false,
             speed: 1000,
             processor: Processor {
-                flags: 0,
+                flags: UNUSED,
                 acc: 0,
                 rx: 0,
                 ry: 0,
-                /// Start at 0x400
-                pc: 0x400,
+                pc: 0,
                 sp: 0,
                 test: 0,
                 info: vec![],
                 clock: 0,
+                cycle: 0,
+                bus: None,
+                halted: false,
+                waiting: false,
+                acc_high: 0,
+                rx_high: 0,
+                ry_high: 0,
+                sp_high: 1,
+                dp: 0,
+                dbr: 0,
+                pbr: 0,
+                emulation: true,
             }
         };
+        // Power on: SP starts at 0 and the reset sequence leaves it at $FD
+        computer.reset();
         computer
     }
 
+    /// Start running at `pc` instead of the reset vector, for images
+    /// whose vectors are not set
+    pub fn set_pc(&mut self, pc: u16) {
+        self.processor.pc = pc;
+        self.cycle = CycleState::new();
+        self.processor.cycle = 0;
+        self.add_info(format!("Starting at: {}", self.symbols.label(pc)));
+    }
+
     pub fn step(&mut self) -> bool {
         while let Some(message) = self.rx.try_iter().next() {
             // Handle messages arriving from the controller.
@@ -79,26 +316,44 @@ impl Computer {
                         self.step = true;
                     }
                 },
-                ControllerMessage::UpdatedProcessorAvailable(processor) => {},
-                ControllerMessage::UpdatedDataAvailable(data) => {},
+                ControllerMessage::UpdatedProcessorAvailable(_) => {},
+                ControllerMessage::UpdatedDataAvailable(_, _) => {},
+                ControllerMessage::UpdatedStackAvailable(_, _) => {},
+                ControllerMessage::UpdatedBanksAvailable(_) => {},
+                ControllerMessage::SetIrq(asserted) => {
+                    self.interrupts.set_irq(asserted);
+                    self.add_info(format!("IRQ line {}", if asserted { "asserted" } else { "released" }));
+                },
+                ControllerMessage::SetNmi(asserted) => {
+                    self.interrupts.set_nmi(asserted);
+                },
+                ControllerMessage::Reset() => {
+                    self.interrupts.reset();
+                },
                 ControllerMessage::GetData() => {
                     let l = self.processor.info.len();
                     if l > 20 {
                         self.processor.info = self.processor.info[l-20..].to_vec();
                     }
                     //println!("{:?}", self.processor);
-                    self.processor.test = self.data[0x200];
-                    self.tx.send(
+                    self.processor.test = self.peek(0x200);
+                    let _ = self.tx.send(
                         ControllerMessage::UpdatedProcessorAvailable(self.processor.clone())
                     );
                     //only send a slice of the data
                     let btm :u16 = if self.processor.pc > 256 { (self.processor.pc - 255) }else {0};
                     let top :u16 = if (self.processor.pc < 0xffff - 256) { self.processor.pc + 256} else { 0xffff };
-                    let mem_to_display = self.data[btm as usize ..=top as usize].to_vec();
+                    let mem_to_display = (btm..=top).map(|address| self.peek(address)).collect();
 
                     
-                    self.tx.send(
-                        ControllerMessage::UpdatedDataAvailable(mem_to_display)
+                    let _ = self.tx.send(
+                        ControllerMessage::UpdatedDataAvailable(btm, mem_to_display)
+                    );
+                    let _ = self.tx.send(
+                        ControllerMessage::UpdatedStackAvailable(self.processor.sp, self.stack_contents())
+                    );
+                    let _ = self.tx.send(
+                        ControllerMessage::UpdatedBanksAvailable(self.bus.banks())
                     );
                 },
             };
@@ -110,7 +365,8 @@ impl Computer {
 
         if (self.paused && self.step) || !self.paused {
             self.step = false;
-            let changed = self.run_instruction();
+            self.advance();
+            self.check_breakpoints();
 
             thread::sleep(time::Duration::from_millis(self.speed));
         }
@@ -118,358 +374,879 @@ impl Computer {
         true
     }
 
+    /// Run instructions without the UI until the program loops on itself,
+    /// like the `jmp *` traps of the test programs. Returns the trap address.
+    pub fn run_until_trap(&mut self) -> u16 {
+        loop {
+            let pc = self.processor.pc;
+            self.next_instruction();
+            if self.processor.pc == pc {
+                return pc;
+            }
+        }
+    }
+
+    /// Run a whole instruction, or interrupt sequence, with the selected core
+    fn next_instruction(&mut self) {
+        self.advance();
+        while self.cycle_stepped && self.cycle.cycle != 0 {
+            self.advance();
+        }
+    }
+
+    /// Run a cycle or an instruction with the selected core, then let the
+    /// devices on the bus catch up and drive the interrupt lines
+    fn advance(&mut self) {
+        let clock = self.processor.clock;
+        if self.cycle_stepped {
+            self.run_cycle();
+        } else {
+            self.run_instruction();
+        }
+        // The clock keeps running while the processor waits or is halted
+        if self.processor.clock == clock {
+            self.processor.clock += 1;
+        }
+        self.bus.tick(self.processor.clock - clock);
+        self.interrupts.set_device_lines(self.bus.irq(), self.bus.nmi());
+    }
+
+    /// Pause when PC gets to the start of an instruction at a breakpoint
+    fn check_breakpoints(&mut self) {
+        let pc = self.processor.pc;
+        if !self.paused && self.cycle.cycle == 0 && self.breakpoints.contains(&pc) {
+            self.paused = true;
+            self.add_info(format!("Breakpoint at: {}", self.symbols.label(pc)));
+        }
+    }
+
+    /// Select the cycle-stepped core, where each step runs a single clock
+    /// cycle and its bus access. A step of the other core runs a whole instruction.
+    pub fn set_cycle_stepped(&mut self, cycle_stepped: bool) {
+        self.cycle_stepped = cycle_stepped;
+    }
+
+    /// Select the processor the computer is built around
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.cpu = variant.cpu();
+        self.add_info(format!("Running as a {}", self.cpu.name()));
+    }
+
+    pub fn processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    /// Name addresses in the debug info with `symbols`
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Pause running when PC gets to one of `breakpoints`
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<u16>) {
+        self.breakpoints = breakpoints;
+    }
+
+    /// Use `address` as a feedback register driving the interrupt lines,
+    /// like the one Klaus Dormann's interrupt test expects. Writing a 1 in
+    /// bit 0 asserts IRQ, a 1 in bit 1 asserts NMI.
+    pub fn set_interrupt_port(&mut self, address: Option<u16>) {
+        self.interrupt_port = address;
+    }
+
+    /// Read memory without going through the processor
+    pub fn peek(&self, address: u16) -> u8 {
+        self.bus.peek(address as u32)
+    }
+
     fn run_instruction(&mut self) {
-        let inst = self.data[(self.processor.pc) as usize];
+        if self.cpu.native_mode() {
+            self.run_native_instruction();
+            return;
+        }
+        if !self.awake() {
+            return;
+        }
+        if self.service_interrupts() {
+            return;
+        }
 
-        match inst {
-            
-            0x10 => {
-                //// println!("Running instruction bpl : {:x?}", inst);
-                self.bpl();
-            },
-            0x18 => {
-                //// println!("Running instruction clc : {:x?}", inst);
-                self.clc();
+        let opcode = self.opcode();
+        let interrupt_flag = self.processor.interrupt();
+        self.processor.clock += self.cpu.cycles(opcode) as u64;
+
+        let (instruction, mode) = self.cpu.decode(opcode);
+        self.execute(instruction, mode);
+        self.update_irq_mask(instruction, interrupt_flag);
+    }
+
+    /// Whether the processor runs, it does not while halted by JAM or STP
+    /// or while WAI waits for an interrupt. A masked IRQ ends WAI without
+    /// being serviced.
+    fn awake(&mut self) -> bool {
+        if self.processor.halted {
+            return self.interrupts.reset_pending();
+        }
+        if self.processor.waiting {
+            let interrupt = self.interrupts.irq() || self.interrupts.nmi_pending() || self.interrupts.reset_pending();
+            self.processor.waiting = !interrupt;
+        }
+        !self.processor.waiting
+    }
+
+    /// Run the instruction at PC, its operand goes through the bus the way
+    /// its `Access` says
+    fn execute(&mut self, instruction: Instruction, mode: Mode) {
+        let name = instruction.name();
+        match instruction.access(mode) {
+            Access::Read => {
+                let val = self.read_operand(mode);
+                self.add_info(format!("Running instruction {}: {:#x} with val: {:#x}", name, self.opcode(), val));
+                self.read_op(instruction, mode, val);
+                self.processor.clock += self.decimal_cycles(instruction) as u64;
+                self.processor.pc = self.processor.pc.wrapping_add(mode.length());
             },
-            0x49 => {
-                //// println!("Running instruction eor : {:x?}", inst);
-                self.eor();
+            Access::Write => {
+                let operand = self.resolve(mode);
+                let (address, val) = self.write_op(instruction, mode, operand.address.unwrap(), operand.page_crossed);
+                self.add_info(format!("Running instruction {}: {:#x} at address: {}", name, self.opcode(), self.symbols.label(address)));
+                self.write(address, val);
+                self.processor.pc = self.processor.pc.wrapping_add(mode.length());
             },
-            0x4c => {
-                //// println!("Running instruction jmp : {:x?}", inst);
-                self.jmp();
+            Access::ReadModifyWrite => {
+                let operand = self.resolve(mode);
+                let target = operand.address.map_or_else(|| "A".to_string(), |address| self.symbols.label(address));
+                self.add_info(format!("Running instruction {}: {:#x} on: {}", name, self.opcode(), target));
+                if self.cpu.cmos() && operand.page_crossed && Self::is_shift(instruction) {
+                    self.processor.clock += 1;
+                }
+                let val = self.load(&operand);
+                let result = self.modify_op(instruction, val);
+                self.store(&operand, result);
+                self.processor.pc = self.processor.pc.wrapping_add(mode.length());
             },
-            0x69 => {
-                //// println!("Running instruction adc : {:x?}", inst);
-                self.adc();
+            Access::Implied if instruction == Instruction::Jam || instruction == Instruction::Stp => self.halt(instruction),
+            Access::Implied if instruction == Instruction::Wai => {
+                self.add_info(format!("Running instruction wai: {:#x}, waiting for an interrupt", self.opcode()));
+                self.processor.waiting = true;
+                self.processor.pc = self.processor.pc.wrapping_add(1);
             },
-            0x88 => {
-                //// println!("Running instruction dey : {:x?}", inst);
-                self.dey();
+            Access::Implied => {
+                self.add_info(format!("Running instruction {}: {:#x}", name, self.opcode()));
+                self.implied_op(instruction);
+                self.processor.pc = self.processor.pc.wrapping_add(1);
             },
-            0x8d => {
-                //// println!("Running instruction sta : {:x?}", inst);
-                self.sta();
+            Access::Branch => self.branch(instruction, mode),
+            Access::Control => match instruction {
+                Instruction::Brk => self.brk(),
+                Instruction::Jmp => self.jmp(mode),
+                Instruction::Jsr => self.jsr(),
+                Instruction::Rts => self.rts(),
+                Instruction::Rti => self.rti(),
+                Instruction::Pha => self.pha(),
+                Instruction::Php => self.php(),
+                Instruction::Pla => self.pla(),
+                Instruction::Plp => self.plp(),
+                Instruction::Phx => self.phx(),
+                Instruction::Phy => self.phy(),
+                Instruction::Plx => self.plx(),
+                Instruction::Ply => self.ply(),
+                _ => unreachable!("{:?} is not a control instruction", instruction),
             },
+        }
+    }
+
+    /// CLI, SEI and PLP only change the I flag after the interrupt
+    /// lines were polled, so the next instruction still runs with the old value
+    fn update_irq_mask(&mut self, instruction: Instruction, interrupt_flag: bool) {
+        self.irq_masked = match instruction {
+            Instruction::Cli | Instruction::Sei | Instruction::Plp => interrupt_flag,
+            _ => self.processor.interrupt(),
+        };
+    }
+
+    /// Run the reset, NMI or IRQ sequence if one is due.
+    /// Returns whether one was run instead of the next instruction.
+    fn service_interrupts(&mut self) -> bool {
+        if self.interrupts.take_reset() {
+            self.reset();
+            return true;
+        }
+
+        if self.interrupts.take_nmi() {
+            self.add_info(format!("Servicing NMI at: {}", self.symbols.label(self.processor.pc)));
+            self.interrupt(self.processor.pc, false, NMI_VECTOR);
+            self.processor.clock += INTERRUPT_CYCLES;
+            return true;
+        }
+
+        if self.interrupts.irq() && !self.irq_masked {
+            self.add_info(format!("Servicing IRQ at: {}", self.symbols.label(self.processor.pc)));
+            self.interrupt(self.processor.pc, false, IRQ_VECTOR);
+            self.processor.clock += INTERRUPT_CYCLES;
+            return true;
+        }
+
+        false
+    }
+
+    /// The reset sequence: set I and load PC from the reset vector.
+    /// It goes through the motions of an interrupt with the stack writes
+    /// turned into reads, so SP is decremented three times.
+    fn reset(&mut self) {
+        self.processor.halted = false;
+        self.cpu.reset();
+        self.bus.reset();
+        self.processor.sp = self.processor.sp.wrapping_sub(3);
+        self.processor.set_interrupt(true);
+        if self.cpu.cmos() {
+            self.processor.set_decimal(false);
+        }
+        self.irq_masked = true;
+        self.processor.pc = self.read_word(RESET_VECTOR);
+        self.processor.clock += INTERRUPT_CYCLES;
+        self.add_info(format!("Reset, starting at: {}", self.symbols.label(self.processor.pc)));
+    }
+
+    /// Push the return address and the status register, then jump through `vector`.
+    /// Shared by BRK, IRQ and NMI, only BRK pushes the status with B set.
+    fn interrupt(&mut self, return_address: u16, break_flag: bool, vector: u16) {
+        self.push_word(return_address);
+        self.push(self.processor.pushed_status(break_flag));
+        self.processor.set_interrupt(true);
+        // The 65C02 also leaves decimal mode
+        if self.cpu.cmos() {
+            self.processor.set_decimal(false);
+        }
+        self.irq_masked = true;
+        self.processor.pc = self.read_word(vector);
+    }
+
+    fn add_info(&mut self, info: String) {
+        let len = self.processor.info.len();
+        if len > 0 && self.processor.info[len-1].msg == info {
+            let last_element = self.processor.info.pop().unwrap();
+            self.processor.info.push(Info {msg: info, qty: last_element.qty + 1});
+        } else {
+            // Only the last few messages are ever displayed, don't let the list grow forever
+            if len >= MAX_INFO {
+                self.processor.info.remove(0);
+            }
+            self.processor.info.push(Info {msg: info, qty: 1});
+        }
+    }
+
+    /// The opcode of the instruction currently being run
+    fn opcode(&self) -> u8 {
+        self.peek(self.processor.pc)
+    }
+
+    /// Resolve the operand of the current instruction
+    fn resolve(&self, mode: Mode) -> Operand {
+        addressing::resolve(mode, self.processor.pc, self.processor.rx, self.processor.ry, self.cpu.cmos(), |addr| self.memory(addr))
+    }
+
+    /// Value of an operand: the byte at its effective address, or the accumulator
+    fn load(&mut self, operand: &Operand) -> u8 {
+        match operand.address {
+            Some(addr) => self.read(addr),
+            None => self.processor.acc,
+        }
+    }
+
+    /// Resolve and load the operand of an instruction that only reads it.
+    /// These take an extra cycle when indexing crosses a page boundary.
+    fn read_operand(&mut self, mode: Mode) -> u8 {
+        let operand = self.resolve(mode);
+        if operand.page_crossed {
+            self.processor.clock += 1;
+        }
+        self.load(&operand)
+    }
+
+    fn store(&mut self, operand: &Operand, val: u8) {
+        match operand.address {
+            Some(addr) => self.write(addr, val),
+            None => self.processor.acc = val,
+        }
+    }
+
+    /// The byte at `address` as the processor sees it, on-chip registers
+    /// included, without the side effects of a read
+    fn memory(&self, address: u16) -> u8 {
+        self.cpu.read_io(address).unwrap_or_else(|| self.peek(address))
+    }
 
-            0x98 => {
-                //// println!("Running instruction tya : {:x?}", inst);
-                self.tya();
+    /// Read a byte from the bus
+    fn read(&mut self, address: u16) -> u8 {
+        let value = match self.cpu.read_io(address) {
+            Some(value) => value,
+            None => self.bus.read(address as u32),
+        };
+        self.processor.bus = Some(BusCycle { address: address as u32, value, write: false });
+        value
+    }
+
+    /// Write a byte to the bus
+    fn write(&mut self, address: u16, value: u8) {
+        self.bus.write(address as u32, value);
+        if let Some(fault) = self.bus.take_fault() {
+            self.add_info(fault);
+        }
+        self.cpu.write_io(address, value);
+        self.processor.bus = Some(BusCycle { address: address as u32, value, write: true });
+        if self.interrupt_port == Some(address) {
+            self.interrupts.set_irq(value & 0b01 != 0);
+            self.interrupts.set_nmi(value & 0b10 != 0);
+        }
+    }
+
+    /// Push a byte on the stack. SP points to the next free slot and wraps
+    /// around within page $01.
+    fn push(&mut self, val: u8) {
+        self.write(STACK_PAGE + self.processor.sp as u16, val);
+        self.processor.sp = self.processor.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.processor.sp = self.processor.sp.wrapping_add(1);
+        self.read(STACK_PAGE + self.processor.sp as u16)
+    }
+
+    /// Push a word on the stack, high byte first
+    fn push_word(&mut self, val: u16) {
+        self.push((val >> 8) as u8);
+        self.push(val as u8);
+    }
+
+    fn pull_word(&mut self) -> u16 {
+        let low_byte = self.pull() as u16;
+        let high_byte = self.pull() as u16;
+        low_byte + (high_byte << 8)
+    }
+
+    /// The bytes currently on the stack, from the most recently pushed one
+    /// (at SP + 1) up to $01FF
+    fn stack_contents(&self) -> Vec<u8> {
+        let top = STACK_PAGE + self.processor.sp as u16 + 1;
+        (top..=0x1ff).map(|address| self.peek(address)).collect()
+    }
+
+    /// Run an instruction that only reads its operand
+    fn read_op(&mut self, instruction: Instruction, mode: Mode, val: u8) {
+        match instruction {
+            Instruction::Adc => self.adc(val),
+            Instruction::And => self.and(val),
+            // BIT # of the 65C02 only sets Z, N and V would come from the opcode stream
+            Instruction::Bit if mode == Mode::Immediate => self.processor.set_zero(self.processor.acc & val == 0),
+            Instruction::Bit => self.bit(val),
+            Instruction::Cmp => self.compare(self.processor.acc, val),
+            Instruction::Cpx => self.compare(self.processor.rx, val),
+            Instruction::Cpy => self.compare(self.processor.ry, val),
+            Instruction::Eor => self.eor(val),
+            Instruction::Lda => self.lda(val),
+            Instruction::Ldx => self.ldx(val),
+            Instruction::Ldy => self.ldy(val),
+            Instruction::Ora => self.ora(val),
+            Instruction::Sbc => self.sbc(val),
+            Instruction::Lax => {
+                self.lda(val);
+                self.processor.rx = val;
             },
-            0x9a => {
-                //// println!("Running instruction txs : {:x?}", inst);
-                self.txs();
+            Instruction::Anc => {
+                self.and(val);
+                self.processor.set_carry(self.processor.negative());
             },
-            0xa0 => {
-                //// println!("Running instruction ldy : {:x?}", inst);
-                self.ldy();
+            Instruction::Alr => {
+                self.and(val);
+                self.processor.acc = self.shift_right(self.processor.acc, 0);
             },
-            0xa2 => {
-                //// println!("Running instruction ldx : {:x?}", inst);
-                self.ldx();
+            Instruction::Arr => self.arr(val),
+            Instruction::Sbx => self.sbx(val),
+            Instruction::Ane => self.lda((self.processor.acc | UNSTABLE_MAGIC) & self.processor.rx & val),
+            Instruction::Lxa => {
+                self.lda((self.processor.acc | UNSTABLE_MAGIC) & val);
+                self.processor.rx = self.processor.acc;
             },
-            0xaa => {
-                //// println!("Running instruction tax : {:x?}", inst);
-                self.tax();
+            Instruction::Las => {
+                self.processor.sp &= val;
+                self.lda(self.processor.sp);
+                self.processor.rx = self.processor.sp;
+            },
+            Instruction::Nop => {},
+            _ => unreachable!("{:?} does not read its operand", instruction),
+        }
+    }
+
+    /// ADC and SBC take one more cycle in decimal mode on the 65C02
+    fn decimal_cycles(&self, instruction: Instruction) -> u8 {
+        let arithmetic = instruction == Instruction::Adc || instruction == Instruction::Sbc;
+        (self.cpu.cmos() && arithmetic && self.processor.decimal()) as u8
+    }
+
+    /// The shifts and rotates, which are quicker than the other read-modify-write
+    /// instructions on the 65C02 when indexing stays in the page
+    fn is_shift(instruction: Instruction) -> bool {
+        matches!(instruction, Instruction::Asl | Instruction::Lsr | Instruction::Rol | Instruction::Ror)
+    }
+
+    /// The address and value a store instruction writes. `address` is the
+    /// effective address and `page_crossed` whether indexing crossed a page.
+    ///
+    /// The unstable SHA, SHX, SHY and TAS AND the value with the high byte of
+    /// the base address plus one. When indexing crossed a page the value they
+    /// write also replaces the high byte of the address.
+    fn write_op(&mut self, instruction: Instruction, mode: Mode, address: u16, page_crossed: bool) -> (u16, u8) {
+        let p = &mut self.processor;
+        let register = match instruction {
+            Instruction::Sta => return (address, p.acc),
+            Instruction::Stx => return (address, p.rx),
+            Instruction::Sty => return (address, p.ry),
+            Instruction::Sax => return (address, p.acc & p.rx),
+            Instruction::Stz => return (address, 0),
+            Instruction::Sha => p.acc & p.rx,
+            Instruction::Shx => p.rx,
+            Instruction::Shy => p.ry,
+            Instruction::Tas => {
+                p.sp = p.acc & p.rx;
+                p.sp
             },
-            0xa9 | 0xad => {
-                //// println!("Running instruction lda : {:x?}", inst);
-                self.lda();
+            _ => unreachable!("{:?} does not write its operand", instruction),
+        };
+        let index = if mode == Mode::AbsoluteX { p.rx } else { p.ry };
+        let high = (address.wrapping_sub(index as u16) >> 8) as u8;
+        let val = register & high.wrapping_add(1);
+        if page_crossed {
+            ((val as u16) << 8 | (address & 0x00ff), val)
+        } else {
+            (address, val)
+        }
+    }
+
+    /// Run a read-modify-write instruction on `val`, returns the value written back
+    fn modify_op(&mut self, instruction: Instruction, val: u8) -> u8 {
+        match instruction {
+            Instruction::Asl => self.shift_left(val, 0),
+            Instruction::Rol => self.shift_left(val, self.processor.carry() as u8),
+            Instruction::Lsr => self.shift_right(val, 0),
+            Instruction::Ror => self.shift_right(val, (self.processor.carry() as u8) << 7),
+            Instruction::Inc => self.inc(val),
+            Instruction::Dec => self.dec(val),
+            Instruction::Slo => {
+                let result = self.shift_left(val, 0);
+                self.ora(result);
+                result
             },
-            0xc0 => {
-                //// println!("Running instruction cmp : {:x?}", inst);
-                self.cpy();
+            Instruction::Rla => {
+                let result = self.shift_left(val, self.processor.carry() as u8);
+                self.and(result);
+                result
             },
-            0xc9 => {
-                //// println!("Running instruction cmp : {:x?}", inst);
-                self.cmp();
+            Instruction::Sre => {
+                let result = self.shift_right(val, 0);
+                self.eor(result);
+                result
             },
-            0xca => {
-                //// println!("Running instruction dex : {:x?}", inst);
-                self.dex();
+            Instruction::Rra => {
+                let result = self.shift_right(val, (self.processor.carry() as u8) << 7);
+                self.adc(result);
+                result
             },
-            0xd0 => {
-                //// println!("Running instruction bne : {:x?}", inst);
-                self.bne();
+            Instruction::Dcp => {
+                let result = val.wrapping_sub(1);
+                self.compare(self.processor.acc, result);
+                result
             },
-            0xd8 => {
-                //// println!("Running instruction cld : {:x?}", inst);
-                self.cld();
+            Instruction::Isc => {
+                let result = val.wrapping_add(1);
+                self.sbc(result);
+                result
             },
-            0xf0 => {
-                //// println!("Running instruction beq : {:x?}", inst);
-                self.beq();
+            // Z is set as by BIT, then the accumulator bits are set or reset in memory
+            Instruction::Tsb => {
+                self.processor.set_zero(self.processor.acc & val == 0);
+                val | self.processor.acc
             },
-            _ => {
-                //// println!("Running instruction nop : {:x?}", inst);
-                self.nop();
+            Instruction::Trb => {
+                self.processor.set_zero(self.processor.acc & val == 0);
+                val & !self.processor.acc
             },
-        };
+            Instruction::Rmb(bit) => val & !(1 << bit),
+            Instruction::Smb(bit) => val | (1 << bit),
+            _ => unreachable!("{:?} does not modify its operand", instruction),
+        }
     }
 
-    fn add_info(&mut self, info: String) {
-        let len = self.processor.info.len();
-        if len > 0 && self.processor.info[len-1].msg == info {
-            let last_element = self.processor.info.pop().unwrap();
-            self.processor.info.push(Info {msg: info, qty: last_element.qty + 1});
+    /// Run an instruction that only works on registers and flags
+    fn implied_op(&mut self, instruction: Instruction) {
+        let p = &mut self.processor;
+        match instruction {
+            Instruction::Clc => p.set_carry(false),
+            Instruction::Sec => p.set_carry(true),
+            Instruction::Cli => p.set_interrupt(false),
+            Instruction::Sei => p.set_interrupt(true),
+            Instruction::Cld => p.set_decimal(false),
+            Instruction::Sed => p.set_decimal(true),
+            Instruction::Clv => p.set_overflow(false),
+            // The only transfer that leaves the flags alone
+            Instruction::Txs => p.sp = p.rx,
+            Instruction::Tsx => { p.rx = p.sp; p.set_zn(p.rx) },
+            Instruction::Tay => { p.ry = p.acc; p.set_zn(p.ry) },
+            Instruction::Tya => { p.acc = p.ry; p.set_zn(p.acc) },
+            Instruction::Tax => { p.rx = p.acc; p.set_zn(p.rx) },
+            Instruction::Txa => { p.acc = p.rx; p.set_zn(p.acc) },
+            Instruction::Inx => { p.rx = p.rx.wrapping_add(1); p.set_zn(p.rx) },
+            Instruction::Iny => { p.ry = p.ry.wrapping_add(1); p.set_zn(p.ry) },
+            Instruction::Dex => { p.rx = p.rx.wrapping_sub(1); p.set_zn(p.rx) },
+            Instruction::Dey => { p.ry = p.ry.wrapping_sub(1); p.set_zn(p.ry) },
+            Instruction::Nop => {},
+            _ => unreachable!("{:?} is not an implied instruction", instruction),
+        }
+    }
+
+    fn adc(&mut self, val: u8) {
+        if !self.processor.decimal() || !self.cpu.decimal() {
+            self.add_with_carry(val);
+        } else if self.cpu.cmos() {
+            // N and Z are valid on the 65C02
+            self.add_decimal(val);
+            self.processor.set_zn(self.processor.acc);
         } else {
-            self.processor.info.push(Info {msg: info, qty: 1});
+            self.add_decimal(val);
         }
     }
 
-    fn adc(&mut self) {
-        let mut acc = self.processor.acc;
-        let val = self.data[(self.processor.pc + 1) as usize];
-        acc += val;
-        self.processor.flags = Self::set_flags(self.processor.flags, acc);
-        self.add_info(format!("Running instruction adc: {:#x} with acc: {:#x} memval: {:#x}", self.data[(self.processor.pc) as usize], self.processor.acc, val));
-        self.processor.acc = acc;
-        self.processor.clock += 2;
-        self.processor.pc += 2;
-    }
-
-    fn cld(&mut self) {
-        self.add_info(format!("Running instruction cld: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.flags = self.processor.flags & 0x7;
-        self.processor.clock += 2;
-    }
-
-    fn txs(&mut self) {
-        self.add_info(format!("Running instruction txs: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.clock += 2;
-        self.processor.sp = self.processor.rx;
-        self.processor.flags = Self::set_flags( self.processor.flags, self.processor.sp);
-        
-    }
-
-    fn tya(&mut self) {
-        self.add_info(format!("Running instruction tya: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.clock += 2;
-        self.processor.acc = self.processor.ry;
-        self.processor.flags = Self::set_flags(self.processor.flags, self.processor.acc);
-        
-    }
-
-    fn clc(&mut self) {
-        self.processor.flags =  self.processor.flags & 0xFE;
-        self.add_info(format!("Running instruction clc: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.clock += 1;
-    }
-
-    fn tax(&mut self) {
-        self.add_info(format!("Running instruction tax: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.flags = Self::set_flags(self.processor.flags, self.processor.acc);
-        self.processor.pc += 1;
-        self.processor.clock += 1;
-        self.processor.rx = self.processor.acc;
-        self.processor.flags = Self::set_flags(self.processor.flags, self.processor.rx);
-        
-    }
-
-    fn eor(&mut self) {
-        let val = self.data[(self.processor.pc + 1) as usize];
-        let mut acc = self.processor.acc;
-        self.add_info(format!("Running instruction eor: {:#x} with acc: {:#x} memval: {:#x}", self.data[(self.processor.pc) as usize], acc, val));
-        //// println!("EOR {:x?} {:x?}", val, acc);
-        
-        acc ^= val;
-        self.processor.pc += 2;
-        self.processor.acc = acc;
-    }
-
-    fn ldx(&mut self) {
-        let x = self.data[(self.processor.pc + 1) as usize];
-        self.add_info(format!("Running instruction ldx: {:#x} with val: {:#x}", self.data[(self.processor.pc) as usize], x));
-        self.processor.rx = x;
-        self.processor.flags = Self::set_flags(self.processor.flags, self.processor.rx);
-        self.processor.pc += 2;
-        
-    }
-
-    fn ldy(&mut self) {
-        self.add_info(format!("Running instruction ldy: {:#x}", self.data[(self.processor.pc) as usize]));
-        let y = self.data[(self.processor.pc + 1) as usize];
-        self.processor.ry = y;
-        self.processor.flags = Self::set_flags(self.processor.flags, self.processor.ry);
-        self.processor.pc += 2;
-        self.processor.clock += 4;
-        
-    }
-
-    fn lda(&mut self) {
-        let mut acc = self.data[(self.processor.pc + 1) as usize];
-        let inst = self.data[(self.processor.pc) as usize];
-        let mut pc = self.processor.pc + 2;
-        let mut info = format!("Running instruction lda: {:#x}", inst);
-        self.processor.clock += 2;
-        if inst == 0xad {
-            //Absolute adressing
-
-            let addr = Self::get_word(&self.data, self.processor.pc + 1);
-            //// println!("inst is absolute addr {:x?}", addr);
-            acc = self.data[addr as usize];
-            pc = self.processor.pc + 3;
-            self.processor.clock += 2;
-            info = format!("Running instruction lda absolute: {:#x}", inst);
+    fn sbc(&mut self, val: u8) {
+        if !self.processor.decimal() || !self.cpu.decimal() {
+            self.add_with_carry(!val);
+        } else if self.cpu.cmos() {
+            self.subtract_decimal_cmos(val);
+        } else {
+            self.subtract_decimal(val);
         }
-        self.add_info(info);
-        self.processor.pc = pc;
-        self.processor.flags = Self::set_flags(self.processor.flags, acc);
     }
 
-    fn dex(&mut self) {
-        self.processor.rx = self.processor.rx.wrapping_sub(1);
-        self.processor.flags = Self::set_flags(self.processor.flags, self.processor.rx);
-        self.add_info(format!("Running instruction dex: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+    fn add_with_carry(&mut self, val: u8) {
+        let acc = self.processor.acc;
+        let sum = acc as u16 + val as u16 + self.processor.carry() as u16;
+        let result = sum as u8;
+        self.processor.set_carry(sum > 0xff);
+        // Overflow when both operands have the same sign and the result has the other one
+        self.processor.set_overflow((acc ^ result) & (val ^ result) & 0x80 != 0);
+        self.processor.set_zn(result);
+        self.processor.acc = result;
     }
 
-    fn dey(&mut self) {
-        self.processor.ry = self.processor.ry.wrapping_sub(1);
-        self.processor.flags = Self::set_flags(self.processor.flags,  self.processor.ry);
-        self.add_info(format!("Running instruction dey: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+    /// NMOS decimal mode addition. Z comes from the binary sum, N and V are
+    /// taken before the high nibble is adjusted, as the real chip does.
+    fn add_decimal(&mut self, val: u8) {
+        let acc = self.processor.acc;
+        let carry = self.processor.carry() as u8;
+        let mut low = (acc & 0x0f) as u16 + (val & 0x0f) as u16 + carry as u16;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (acc & 0xf0) as u16 + (val & 0xf0) as u16 + low;
+        let intermediate = sum as u8;
+        self.processor.set_zero(acc.wrapping_add(val).wrapping_add(carry) == 0);
+        self.processor.set_negative(intermediate & 0x80 != 0);
+        self.processor.set_overflow((acc ^ intermediate) & (val ^ intermediate) & 0x80 != 0);
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        self.processor.set_carry(sum > 0xff);
+        self.processor.acc = sum as u8;
     }
 
-    fn cmp(&mut self) {
-        self.add_info(format!("Running instruction cmp: {:#x}", self.data[(self.processor.pc) as usize]));
+    /// NMOS decimal mode subtraction. All flags are set as in binary mode,
+    /// only the accumulator gets the decimal result.
+    fn subtract_decimal(&mut self, val: u8) {
         let acc = self.processor.acc;
-        let value = self.data[(self.processor.pc + 1) as usize];
-        let result: u8 = acc.wrapping_sub(value);
-        let mut flags = self.processor.flags;
-        if (acc > value) {
-            flags |= 1;
-        }
-        flags = Self::set_flags(flags, result as u8);
-        self.processor.flags = flags;
-        self.processor.pc += 2;
-        self.processor.clock += 4;
-        
-    }
-
-    fn cpy(&mut self) {
-        self.add_info(format!("Running instruction cpy: {:#x}", self.data[(self.processor.pc) as usize]));
-        let ry = self.processor.ry;
-        let value = self.data[(self.processor.pc + 1) as usize];
-        let result: u8 = ry.wrapping_sub(value);
-        let mut flags = self.processor.flags;
-        if (ry > value) {
-            flags |= 1;
-        }
-        flags = Self::set_flags(flags, result as u8);
-        self.processor.flags = flags;
-        self.processor.pc += 2;
-        self.processor.clock += 4;
-        
-    }
-
-    fn sta(&mut self) {
-        let addr = Self::get_word(&self.data, self.processor.pc + 1);
-    // // println!("sta addr 0x{:x?}", addr);
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = self.processor.acc;
-        self.data = _addr;
-
-        self.add_info(format!("Running instruction sta: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 3;
-        self.processor.clock += 5;
-    }
-
-    fn jmp(&mut self) {
-        self.add_info(format!("Running instruction jmp: {:#x}", self.data[(self.processor.pc) as usize]));
-        let addr = Self::get_word(&self.data, self.processor.pc + 1);
-        //// println!("Jumping to 0x{:x?}", addr);
-        self.processor.pc = addr;
+        let borrow = !self.processor.carry() as i16;
+        let mut low = (acc & 0x0f) as i16 - (val & 0x0f) as i16 - borrow;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0f) - 0x10;
+        }
+        let mut result = (acc & 0xf0) as i16 - (val & 0xf0) as i16 + low;
+        if result < 0 {
+            result -= 0x60;
+        }
+        self.add_with_carry(!val);
+        self.processor.acc = result as u8;
     }
 
-    fn bne(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+    /// AND then ROR the accumulator, with C and V coming from bits 6 and 5
+    /// of the result. In decimal mode the result gets a BCD style fixup.
+    fn arr(&mut self, val: u8) {
+        let and = self.processor.acc & val;
+        let carry_in = (self.processor.carry() as u8) << 7;
+        let mut result = (and >> 1) | carry_in;
+        self.processor.set_zn(result);
+
+        if !self.processor.decimal() || !self.cpu.decimal() {
+            self.processor.set_carry(result & 0x40 != 0);
+            self.processor.set_overflow(((result >> 6) ^ (result >> 5)) & 1 != 0);
+        } else {
+            // N is the old carry and V comes from the change in bit 6, both
+            // before the fixup. C is set when the high nibble is fixed.
+            self.processor.set_overflow((and ^ result) & 0x40 != 0);
+            if (and & 0x0f) + (and & 0x01) > 5 {
+                result = (result & 0xf0) | (result.wrapping_add(6) & 0x0f);
+            }
+            let fix_high = (and >> 4) + ((and >> 4) & 0x01) > 5;
+            if fix_high {
+                result = result.wrapping_add(0x60);
+            }
+            self.processor.set_carry(fix_high);
+        }
+        self.processor.acc = result;
+    }
 
-        let should_jump = (self.processor.flags >> 1) & 1 == 0;
-        let mut new_addr :u16;
-        new_addr = self.processor.pc + 2;
-        let mut info = format!("Running instruction bne not jumping: {:#x}", self.data[(self.processor.pc) as usize]);
+    /// X = (A AND X) - operand, without borrow and ignoring decimal mode.
+    /// Flags are set as by CMP.
+    fn sbx(&mut self, val: u8) {
+        let and = self.processor.acc & self.processor.rx;
+        self.compare(and, val);
+        self.processor.rx = and.wrapping_sub(val);
+    }
 
-        if (should_jump) {
-            let rel_address = offset as i8;
-            // // println!("Jumping offset {:?}", rel_address);
-            new_addr = ((new_addr as i32) + (rel_address as i32)) as u16;
-            info = format!("Running instruction bne {:#x} jumping to: {:#x}", self.data[(self.processor.pc) as usize], new_addr);
+    /// 65C02 decimal mode subtraction. C and V are set as in binary mode,
+    /// N and Z from the decimal result. The low nibble is adjusted after the
+    /// high one, which only makes a difference with invalid BCD operands.
+    fn subtract_decimal_cmos(&mut self, val: u8) {
+        let acc = self.processor.acc;
+        let borrow = !self.processor.carry() as i16;
+        let low = (acc & 0x0f) as i16 - (val & 0x0f) as i16 - borrow;
+        let mut result = acc as i16 - val as i16 - borrow;
+        if result < 0 {
+            result -= 0x60;
+        }
+        if low < 0 {
+            result -= 0x06;
         }
+        self.add_with_carry(!val);
+        self.processor.acc = result as u8;
+        self.processor.set_zn(self.processor.acc);
+    }
 
-        self.processor.clock += 3;
-        self.processor.pc = new_addr;
-        self.add_info(info);
+    fn and(&mut self, val: u8) {
+        self.processor.acc &= val;
+        self.processor.set_zn(self.processor.acc);
     }
 
+    fn ora(&mut self, val: u8) {
+        self.processor.acc |= val;
+        self.processor.set_zn(self.processor.acc);
+    }
 
-    fn beq(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
-        // // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
-        let should_jump = (self.processor.flags >> 1) & 1 == 1;
-        let mut new_addr :u16;
-        let mut info = format!("Running instruction beq not jumping: {:#x}", self.data[(self.processor.pc) as usize]);
-        new_addr = self.processor.pc + 2;
-        if (should_jump) {
-            let rel_address = offset as i8;
-            // // println!("Jumping offset {:?}", rel_address);
-            new_addr = ((new_addr as i32) + (rel_address as i32)) as u16;
-            info = format!("Running instruction beq {:#x} jumping to: {:#x}", self.data[(self.processor.pc) as usize], new_addr);
-        }
-        self.processor.clock += 3;
-        self.processor.pc = new_addr;
-        self.add_info(info);
+    fn eor(&mut self, val: u8) {
+        self.processor.acc ^= val;
+        self.processor.set_zn(self.processor.acc);
     }
 
-    fn bpl(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
-        // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
-        let should_jump = (self.processor.flags >> 7) & 1 == 0;
-        let mut new_addr :u16;
-        new_addr = self.processor.pc + 2;
-        let mut info = format!("Running instruction bpl not jumping: {:#x}", self.data[(self.processor.pc) as usize]);
-        if (should_jump) {
-            let rel_address = offset as i8;
-            // println!("BPL Jumping offset {:?}", rel_address);
-            new_addr = ((new_addr as i32) + (rel_address as i32)) as u16;
-            info = format!("Running instruction bpl {:#x} jumping to: {:#x}", self.data[(self.processor.pc) as usize], new_addr);
-        }
-        self.processor.pc = new_addr;
-        self.processor.clock += 3;
-        self.add_info(info);
+    fn bit(&mut self, val: u8) {
+        // N and V are copied from the memory value, Z comes from acc & value
+        self.processor.set_negative(val & NEGATIVE != 0);
+        self.processor.set_overflow(val & OVERFLOW != 0);
+        self.processor.set_zero(self.processor.acc & val == 0);
     }
 
-    fn nop(&mut self) {
-        self.add_info(format!("Running instruction nop: {:#x}", self.data[(self.processor.pc) as usize]));
-        self.processor.pc += 1;
-        self.processor.clock += 2;
-        
+    fn lda(&mut self, val: u8) {
+        self.processor.acc = val;
+        self.processor.set_zn(val);
     }
 
-    pub fn set_flags(flags:u8, val:u8) -> u8 {
-        let mut _flags = flags;
-        if val == 0 {
-            //Set zero flag
-            _flags |= 0b10;
-        } else {
-            _flags &= 0b11111101;
+    fn ldx(&mut self, val: u8) {
+        self.processor.rx = val;
+        self.processor.set_zn(val);
+    }
+
+    fn ldy(&mut self, val: u8) {
+        self.processor.ry = val;
+        self.processor.set_zn(val);
+    }
+
+    /// Shift `val` left, putting `carry_in` in bit 0 and bit 7 in the carry flag
+    fn shift_left(&mut self, val: u8, carry_in: u8) -> u8 {
+        let result = (val << 1) | carry_in;
+        self.processor.set_carry(val & 0x80 != 0);
+        self.processor.set_zn(result);
+        result
+    }
+
+    /// Shift `val` right, putting `carry_in` in bit 7 and bit 0 in the carry flag
+    fn shift_right(&mut self, val: u8, carry_in: u8) -> u8 {
+        let result = (val >> 1) | carry_in;
+        self.processor.set_carry(val & 1 != 0);
+        self.processor.set_zn(result);
+        result
+    }
+
+    fn inc(&mut self, val: u8) -> u8 {
+        let result = val.wrapping_add(1);
+        self.processor.set_zn(result);
+        result
+    }
+
+    fn dec(&mut self, val: u8) -> u8 {
+        let result = val.wrapping_sub(1);
+        self.processor.set_zn(result);
+        result
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        let result: u8 = register.wrapping_sub(value);
+        self.processor.set_carry(register >= value);
+        self.processor.set_zn(result);
+    }
+
+    fn pha(&mut self) {
+        self.add_info(format!("Running instruction pha: {:#x}", self.opcode()));
+        self.push(self.processor.acc);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn pla(&mut self) {
+        self.add_info(format!("Running instruction pla: {:#x}", self.opcode()));
+        self.processor.acc = self.pull();
+        self.processor.set_zn(self.processor.acc);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn php(&mut self) {
+        self.add_info(format!("Running instruction php: {:#x}", self.opcode()));
+        self.push(self.processor.pushed_status(true));
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn plp(&mut self) {
+        self.add_info(format!("Running instruction plp: {:#x}", self.opcode()));
+        let status = self.pull();
+        self.processor.set_pulled_status(status);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn phx(&mut self) {
+        self.add_info(format!("Running instruction phx: {:#x}", self.opcode()));
+        self.push(self.processor.rx);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn phy(&mut self) {
+        self.add_info(format!("Running instruction phy: {:#x}", self.opcode()));
+        self.push(self.processor.ry);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn plx(&mut self) {
+        self.add_info(format!("Running instruction plx: {:#x}", self.opcode()));
+        self.processor.rx = self.pull();
+        self.processor.set_zn(self.processor.rx);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn ply(&mut self) {
+        self.add_info(format!("Running instruction ply: {:#x}", self.opcode()));
+        self.processor.ry = self.pull();
+        self.processor.set_zn(self.processor.ry);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+    }
+
+    fn jmp(&mut self, mode: Mode) {
+        let operand = self.resolve(mode);
+        let addr = operand.address.unwrap();
+        self.add_info(format!("Running instruction jmp: {:#x} jumping to: {}", self.opcode(), self.symbols.label(addr)));
+        self.processor.pc = addr;
+    }
+
+    fn jsr(&mut self) {
+        let addr = self.resolve(Mode::Absolute).address.unwrap();
+        self.add_info(format!("Running instruction jsr: {:#x} jumping to: {}", self.opcode(), self.symbols.label(addr)));
+        // The return address pushed is the last byte of the jsr instruction
+        let ret = self.processor.pc.wrapping_add(2);
+        self.push_word(ret);
+        self.processor.pc = addr;
+    }
+
+    fn rts(&mut self) {
+        let addr = self.pull_word().wrapping_add(1);
+        self.add_info(format!("Running instruction rts: {:#x} returning to: {}", self.opcode(), self.symbols.label(addr)));
+        self.processor.pc = addr;
+    }
+
+    fn brk(&mut self) {
+        self.add_info(format!("Running instruction brk: {:#x}", self.opcode()));
+        // brk is followed by a padding byte, skipped on return
+        let ret = self.processor.pc.wrapping_add(2);
+        self.interrupt(ret, true, IRQ_VECTOR);
+    }
+
+    fn rti(&mut self) {
+        let status = self.pull();
+        self.processor.set_pulled_status(status);
+        let addr = self.pull_word();
+        self.add_info(format!("Running instruction rti: {:#x} returning to: {}", self.opcode(), self.symbols.label(addr)));
+        self.processor.pc = addr;
+    }
+
+    /// Whether the condition of a branch instruction holds. `tested` is the
+    /// zero page byte tested by BBR and BBS.
+    fn branch_taken(&self, instruction: Instruction, tested: u8) -> bool {
+        match instruction {
+            Instruction::Bra => true,
+            Instruction::Bbr(bit) => tested & (1 << bit) == 0,
+            Instruction::Bbs(bit) => tested & (1 << bit) != 0,
+            Instruction::Bne => !self.processor.zero(),
+            Instruction::Beq => self.processor.zero(),
+            Instruction::Bpl => !self.processor.negative(),
+            Instruction::Bmi => self.processor.negative(),
+            Instruction::Bcc => !self.processor.carry(),
+            Instruction::Bcs => self.processor.carry(),
+            Instruction::Bvc => !self.processor.overflow(),
+            Instruction::Bvs => self.processor.overflow(),
+            _ => unreachable!("{:?} is not a branch", instruction),
         }
-        if (val >> 7 == 1) {
-            _flags |= 0b10000000;
+    }
+
+    /// Relative branches, and BBR and BBS which first read a zero page byte
+    fn branch(&mut self, instruction: Instruction, mode: Mode) {
+        let name = instruction.name();
+        let operand = self.resolve(mode);
+        let mut new_addr = self.processor.pc.wrapping_add(mode.length());
+        let mut info = format!("Running instruction {} not jumping: {:#x}", name, self.opcode());
+        let tested = match mode {
+            Mode::ZeroPageRelative => {
+                let zero_page = self.peek(self.processor.pc.wrapping_add(1));
+                self.memory(zero_page as u16)
+            },
+            _ => 0,
+        };
+
+        if self.branch_taken(instruction, tested) {
+            new_addr = operand.address.unwrap();
+            // One more cycle to take the branch, and another one to fix the
+            // high byte of PC when the target is on another page
+            self.processor.clock += 1;
+            if operand.page_crossed {
+                self.processor.clock += 1;
+            }
+            info = format!("Running instruction {} {:#x} jumping to: {}", name, self.opcode(), self.symbols.label(new_addr));
         }
-        // // println!("Setting flags to {:#b}", _flags);
-        return _flags;
+
+        self.processor.pc = new_addr;
+        self.add_info(info);
+    }
+
+    /// Stop the processor after JAM or STP, PC stays on the opcode
+    fn halt(&mut self, instruction: Instruction) {
+        self.add_info(format!("Processor halted by {}: {:#x} at: {}", instruction.name(), self.opcode(), self.symbols.label(self.processor.pc)));
+        self.processor.halted = true;
     }
 
-    pub fn get_word(data: &Vec<u8>, address: u16) -> u16 {
-        let low_byte :u16 = data[(address) as usize].into();
-        let high_byte :u16 = data[(address + 1) as usize].into();
-        return low_byte + (high_byte << 8);
+    /// Little endian word read at `address`, a word at $FFFF has its high
+    /// byte at $0000
+    fn read_word(&mut self, address: u16) -> u16 {
+        let low_byte = self.read(address) as u16;
+        let high_byte = self.read(address.wrapping_add(1)) as u16;
+        low_byte + (high_byte << 8)
     }
-}
\ No newline at end of file
+}
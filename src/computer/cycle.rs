@@ -0,0 +1,584 @@
+//! The cycle-stepped core.
+//!
+//! Each call to `run_cycle` runs one clock cycle, with the single bus access
+//! the processor does on it. Dummy reads, the double write of read-modify-write
+//! instructions and the stack accesses all happen on the same cycle as on the
+//! real chip, following the cycle tables of "64doc".
+//!
+//! It runs every variant but the 65C816, which has a core of its own. The
+//! 65C02 differences, like the extra cycle of `JMP (abs)`, are asked of the
+//! `Cpu`.
+
+use crate::addressing::AddressingMode as Mode;
+use crate::bus::Bus;
+use crate::instructions::{Access, Instruction};
+use crate::interrupts::{IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+
+use super::{Computer, STACK_PAGE};
+
+/// What the processor is running
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sequence {
+    Instruction,
+    /// IRQ or NMI: a BRK that does not move PC and pushes the status with B clear
+    Interrupt,
+    Reset,
+}
+
+/// What the cycle-stepped core keeps between the cycles of an instruction
+#[derive(Clone, Copy, Debug)]
+pub struct CycleState {
+    /// Cycle of the current sequence, from 1 for the opcode fetch, 0 between instructions
+    pub cycle: u8,
+    sequence: Sequence,
+    opcode: u8,
+    instruction: Instruction,
+    mode: Mode,
+    /// The I flag when the opcode was fetched
+    interrupt_flag: bool,
+    /// Effective address, or jump target, put together a byte at a time
+    address: u16,
+    /// Zero page pointer of the indirect addressing modes, byte tested by BBR and BBS
+    pointer: u8,
+    /// Operand of read-modify-write instructions, offset of branches
+    value: u8,
+    /// Whether adding the index carried into the high byte of the address
+    page_crossed: bool,
+    /// First cycle once the effective address is known, 0 until then
+    operate_from: u8,
+    /// Vector of the interrupt sequence
+    vector: u16,
+    /// Dummy cycles left once the instruction did its work
+    extra: u8,
+}
+
+impl CycleState {
+    pub fn new() -> CycleState {
+        CycleState {
+            cycle: 0,
+            sequence: Sequence::Instruction,
+            opcode: 0,
+            instruction: Instruction::Nop,
+            mode: Mode::Implied,
+            interrupt_flag: false,
+            address: 0,
+            pointer: 0,
+            value: 0,
+            page_crossed: false,
+            operate_from: 0,
+            vector: 0,
+            extra: 0,
+        }
+    }
+}
+
+impl<B: Bus> Computer<B> {
+    /// Run a single clock cycle
+    pub(super) fn run_cycle(&mut self) {
+        if self.cycle.cycle == 0 && !self.awake() {
+            return;
+        }
+        self.processor.clock += 1;
+        self.cycle.cycle += 1;
+
+        let done = if self.cycle.cycle == 1 {
+            self.fetch_cycle()
+        } else if self.cycle.extra > 0 {
+            self.extra_cycle()
+        } else {
+            match self.cycle.sequence {
+                Sequence::Instruction => self.instruction_cycle(),
+                Sequence::Interrupt => self.interrupt_cycle(),
+                Sequence::Reset => self.reset_cycle(),
+            }
+        };
+
+        if done {
+            match self.cycle.sequence {
+                Sequence::Instruction => self.update_irq_mask(self.cycle.instruction, self.cycle.interrupt_flag),
+                _ => self.irq_masked = true,
+            }
+            self.cycle.cycle = 0;
+        }
+        self.processor.cycle = self.cycle.cycle;
+    }
+
+    /// Fetch the next opcode. When an interrupt is due the opcode is
+    /// fetched all the same, then dropped and the interrupt sequence starts.
+    fn fetch_cycle(&mut self) -> bool {
+        let pc = self.processor.pc;
+        let opcode = self.read(pc);
+        self.cycle.operate_from = 0;
+        self.cycle.page_crossed = false;
+        self.cycle.extra = 0;
+
+        if self.interrupts.take_reset() {
+            self.cycle.sequence = Sequence::Reset;
+        } else if self.interrupts.take_nmi() {
+            self.add_info(format!("Servicing NMI at: {}", self.symbols.label(pc)));
+            self.cycle.sequence = Sequence::Interrupt;
+            self.cycle.vector = NMI_VECTOR;
+        } else if self.interrupts.irq() && !self.irq_masked {
+            self.add_info(format!("Servicing IRQ at: {}", self.symbols.label(pc)));
+            self.cycle.sequence = Sequence::Interrupt;
+            self.cycle.vector = IRQ_VECTOR;
+        } else {
+            let (instruction, mode) = self.cpu.decode(opcode);
+            self.cycle.sequence = Sequence::Instruction;
+            self.cycle.opcode = opcode;
+            self.cycle.instruction = instruction;
+            self.cycle.mode = mode;
+            self.cycle.interrupt_flag = self.processor.interrupt();
+            self.processor.pc = pc.wrapping_add(1);
+            self.add_info(format!("Fetched instruction {}: {:#x} at: {}", instruction.name(), opcode, self.symbols.label(pc)));
+            // The single byte NOPs of the 65C02 are over already
+            return self.cpu.cycles(opcode) == 1;
+        }
+        false
+    }
+
+    /// Run a read instruction on its operand and tell whether it is over.
+    /// ADC and SBC in decimal mode on the 65C02, and the NOPs that take
+    /// longer than their addressing mode, go on with dummy cycles.
+    fn finish_read(&mut self, val: u8) -> bool {
+        let instruction = self.cycle.instruction;
+        self.read_op(instruction, self.cycle.mode, val);
+        self.cycle.extra = self.decimal_cycles(instruction);
+        if instruction == Instruction::Nop {
+            self.cycle.extra += self.cpu.cycles(self.cycle.opcode).saturating_sub(self.cycle.cycle);
+        }
+        self.cycle.extra == 0
+    }
+
+    /// A dummy cycle at the end of an instruction, it reads the last address again
+    fn extra_cycle(&mut self) -> bool {
+        let address = self.processor.bus.map_or(self.processor.pc, |bus| bus.address as u16);
+        self.read(address);
+        self.cycle.extra -= 1;
+        self.cycle.extra == 0
+    }
+
+    fn instruction_cycle(&mut self) -> bool {
+        let cycle = self.cycle.cycle;
+        match self.cycle.instruction.access(self.cycle.mode) {
+            Access::Implied => self.implied_cycle(),
+            Access::Branch => self.branch_cycle(),
+            Access::Control => self.control_cycle(),
+            _ if self.cycle.operate_from == 0 => self.address_cycle(),
+            _ => self.operate_cycle(cycle - self.cycle.operate_from),
+        }
+    }
+
+    /// Read the byte at PC and move past it
+    fn fetch_operand(&mut self) -> u8 {
+        let val = self.read(self.processor.pc);
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+        val
+    }
+
+    fn push_cycle(&mut self, val: u8) {
+        self.write(STACK_PAGE + self.processor.sp as u16, val);
+        self.processor.sp = self.processor.sp.wrapping_sub(1);
+    }
+
+    fn pull_cycle(&mut self) -> u8 {
+        self.processor.sp = self.processor.sp.wrapping_add(1);
+        self.read(STACK_PAGE + self.processor.sp as u16)
+    }
+
+    /// The stack is read while SP is incremented, before the first pull
+    fn stack_dummy_read(&mut self) {
+        self.read(STACK_PAGE + self.processor.sp as u16);
+    }
+
+    /// The index register of the current addressing mode
+    fn index(&self) -> u8 {
+        match self.cycle.mode {
+            Mode::ZeroPageY | Mode::AbsoluteY | Mode::IndirectY => self.processor.ry,
+            _ => self.processor.rx,
+        }
+    }
+
+    /// Add the index to the low byte of the address. The carry into the high
+    /// byte only happens on the next cycle, after a read at the unfixed address.
+    fn add_index(&mut self, high: u8) {
+        let (low, carry) = (self.cycle.address as u8).overflowing_add(self.index());
+        self.cycle.address = (high as u16) << 8 | low as u16;
+        self.cycle.page_crossed = carry;
+    }
+
+    /// One cycle of working out the effective address
+    fn address_cycle(&mut self) -> bool {
+        let cycle = self.cycle.cycle;
+        let instruction = self.cycle.instruction;
+        match (self.cycle.mode, cycle) {
+            (Mode::Immediate, _) => {
+                let val = self.fetch_operand();
+                return self.finish_read(val);
+            },
+            (Mode::Accumulator, _) => {
+                self.read(self.processor.pc);
+                self.processor.acc = self.modify_op(instruction, self.processor.acc);
+                return true;
+            },
+            (Mode::ZeroPage, _) => {
+                self.cycle.address = self.fetch_operand() as u16;
+                self.cycle.operate_from = cycle + 1;
+            },
+            (Mode::ZeroPageX, 2) | (Mode::ZeroPageY, 2)
+            | (Mode::Absolute, 2) | (Mode::AbsoluteX, 2) | (Mode::AbsoluteY, 2) => {
+                self.cycle.address = self.fetch_operand() as u16;
+            },
+            (Mode::ZeroPageX, _) | (Mode::ZeroPageY, _) => {
+                // The base address is read while the index is added, the sum stays in page zero
+                self.read(self.cycle.address);
+                self.cycle.address = (self.cycle.address as u8).wrapping_add(self.index()) as u16;
+                self.cycle.operate_from = cycle + 1;
+            },
+            (Mode::Absolute, _) => {
+                self.cycle.address |= (self.fetch_operand() as u16) << 8;
+                self.cycle.operate_from = cycle + 1;
+            },
+            (Mode::AbsoluteX, 3) | (Mode::AbsoluteY, 3) => {
+                let high = self.fetch_operand();
+                self.add_index(high);
+            },
+            (Mode::IndirectX, 2) | (Mode::IndirectY, 2) | (Mode::ZeroPageIndirect, 2) => {
+                self.cycle.pointer = self.fetch_operand();
+            },
+            (Mode::IndirectX, 3) => {
+                self.read(self.cycle.pointer as u16);
+                self.cycle.pointer = self.cycle.pointer.wrapping_add(self.processor.rx);
+            },
+            (Mode::IndirectX, 4) | (Mode::IndirectY, 3) | (Mode::ZeroPageIndirect, 3) => {
+                self.cycle.address = self.read(self.cycle.pointer as u16) as u16;
+            },
+            (Mode::IndirectX, _) | (Mode::ZeroPageIndirect, _) => {
+                // The pointer wraps around within page zero
+                let high = self.read(self.cycle.pointer.wrapping_add(1) as u16);
+                self.cycle.address |= (high as u16) << 8;
+                self.cycle.operate_from = cycle + 1;
+            },
+            (Mode::IndirectY, 4) => {
+                let high = self.read(self.cycle.pointer.wrapping_add(1) as u16);
+                self.add_index(high);
+            },
+            (Mode::AbsoluteX, _) | (Mode::AbsoluteY, _) | (Mode::IndirectY, _) => {
+                let cmos = self.cpu.cmos();
+                let access = instruction.access(self.cycle.mode);
+                if cmos && !self.cycle.page_crossed && access == Access::ReadModifyWrite && Self::is_shift(instruction) {
+                    // The 65C02 shifts skip the fixup when there is nothing to fix
+                    self.cycle.operate_from = cycle;
+                    return self.operate_cycle(0);
+                }
+
+                // Read at the address before its high byte is fixed. When it
+                // did not need fixing this is the operand of a read instruction.
+                // The 65C02 reads the last operand byte again instead of a wrong address.
+                let val = if cmos && self.cycle.page_crossed {
+                    self.read(self.processor.pc.wrapping_sub(1))
+                } else {
+                    self.read(self.cycle.address)
+                };
+                if self.cycle.page_crossed {
+                    self.cycle.address = self.cycle.address.wrapping_add(0x100);
+                } else if access == Access::Read {
+                    return self.finish_read(val);
+                }
+                self.cycle.operate_from = cycle + 1;
+            },
+            (mode, _) => unreachable!("{:?} does not address memory", mode),
+        }
+        false
+    }
+
+    /// One cycle of reading or writing the operand, `step` counts from 0
+    /// once the effective address is known
+    fn operate_cycle(&mut self, step: u8) -> bool {
+        let instruction = self.cycle.instruction;
+        let address = self.cycle.address;
+        match (instruction.access(self.cycle.mode), step) {
+            (Access::Read, _) => {
+                let val = self.read(address);
+                self.finish_read(val)
+            },
+            (Access::Write, _) => {
+                let (address, val) = self.write_op(instruction, self.cycle.mode, address, self.cycle.page_crossed);
+                self.write(address, val);
+                true
+            },
+            (_, 0) => {
+                self.cycle.value = self.read(address);
+                false
+            },
+            (_, 1) => {
+                // The unmodified value is written back while it goes through
+                // the ALU, the 65C02 reads it again instead
+                if self.cpu.cmos() {
+                    self.read(address);
+                } else {
+                    self.write(address, self.cycle.value);
+                }
+                self.cycle.value = self.modify_op(instruction, self.cycle.value);
+                false
+            },
+            _ => {
+                self.write(address, self.cycle.value);
+                true
+            },
+        }
+    }
+
+    fn implied_cycle(&mut self) -> bool {
+        // The byte after the opcode is read and ignored
+        self.read(self.processor.pc);
+        let instruction = self.cycle.instruction;
+        match instruction {
+            // WAI and STP take one more cycle before stopping
+            Instruction::Wai | Instruction::Stp if self.cycle.cycle == 2 => false,
+            Instruction::Jam | Instruction::Stp => {
+                self.processor.pc = self.processor.pc.wrapping_sub(1);
+                self.halt(instruction);
+                true
+            },
+            Instruction::Wai => {
+                self.add_info(format!("Waiting for an interrupt at: {}", self.symbols.label(self.processor.pc)));
+                self.processor.waiting = true;
+                true
+            },
+            _ => {
+                self.implied_op(instruction);
+                true
+            },
+        }
+    }
+
+    fn branch_cycle(&mut self) -> bool {
+        let mut cycle = self.cycle.cycle;
+        if self.cycle.mode == Mode::ZeroPageRelative {
+            // BBR and BBS read the tested byte twice before going on like
+            // the other branches
+            match cycle {
+                2 => {
+                    self.cycle.address = self.fetch_operand() as u16;
+                    return false;
+                },
+                3 => {
+                    self.cycle.pointer = self.read(self.cycle.address);
+                    return false;
+                },
+                4 => {
+                    self.read(self.cycle.address);
+                    return false;
+                },
+                _ => cycle -= 3,
+            }
+        }
+
+        match cycle {
+            2 => {
+                self.cycle.value = self.fetch_operand();
+                !self.branch_taken(self.cycle.instruction, self.cycle.pointer)
+            },
+            3 => {
+                // The next opcode is read while the offset is added to the low byte of PC
+                let pc = self.processor.pc;
+                self.read(pc);
+                let target = pc.wrapping_add(self.cycle.value as i8 as u16);
+                self.cycle.address = target;
+                self.processor.pc = (pc & 0xff00) | (target & 0x00ff);
+                self.processor.pc == target
+            },
+            _ => {
+                // Then read from the wrong page while the high byte is fixed
+                self.read(self.processor.pc);
+                self.processor.pc = self.cycle.address;
+                true
+            },
+        }
+    }
+
+    fn control_cycle(&mut self) -> bool {
+        let pc = self.processor.pc;
+        match (self.cycle.instruction, self.cycle.cycle) {
+            (Instruction::Brk, 2) => {
+                // The padding byte, skipped on return
+                self.fetch_operand();
+                self.cycle.vector = IRQ_VECTOR;
+            },
+            (Instruction::Brk, _) => return self.interrupt_cycle(),
+            (Instruction::Jmp, 2) | (Instruction::Jsr, 2) => {
+                self.cycle.address = self.fetch_operand() as u16;
+            },
+            (Instruction::Jmp, 3) => {
+                self.cycle.address |= (self.fetch_operand() as u16) << 8;
+                if self.cycle.mode == Mode::Absolute {
+                    self.processor.pc = self.cycle.address;
+                    return true;
+                }
+            },
+            (Instruction::Jmp, 4) if self.cpu.cmos() => {
+                // The 65C02 takes one more cycle, to add X or to get the
+                // pointer right
+                self.read(pc.wrapping_sub(1));
+                if self.cycle.mode == Mode::AbsoluteIndirectX {
+                    self.cycle.address = self.cycle.address.wrapping_add(self.processor.rx as u16);
+                }
+            },
+            (Instruction::Jmp, 5) if self.cpu.cmos() => {
+                self.cycle.value = self.read(self.cycle.address);
+            },
+            (Instruction::Jmp, _) if self.cpu.cmos() => {
+                let high = self.read(self.cycle.address.wrapping_add(1));
+                self.processor.pc = (high as u16) << 8 | self.cycle.value as u16;
+                return true;
+            },
+            (Instruction::Jmp, 4) => {
+                self.cycle.value = self.read(self.cycle.address);
+            },
+            (Instruction::Jmp, _) => {
+                // Only the low byte of the pointer is incremented, so a
+                // pointer at $xxFF gets its high byte from $xx00
+                let pointer = self.cycle.address;
+                let high = self.read((pointer & 0xff00) | (pointer.wrapping_add(1) & 0x00ff));
+                self.processor.pc = (high as u16) << 8 | self.cycle.value as u16;
+                return true;
+            },
+            (Instruction::Jsr, 3) => self.stack_dummy_read(),
+            (Instruction::Jsr, 4) => self.push_cycle((pc >> 8) as u8),
+            (Instruction::Jsr, 5) => self.push_cycle(pc as u8),
+            (Instruction::Jsr, _) => {
+                let high = self.read(pc);
+                self.processor.pc = (high as u16) << 8 | self.cycle.address;
+                return true;
+            },
+            (_, 2) => {
+                self.read(pc);
+            },
+            (Instruction::Pha, _) => {
+                self.push_cycle(self.processor.acc);
+                return true;
+            },
+            (Instruction::Php, _) => {
+                self.push_cycle(self.processor.pushed_status(true));
+                return true;
+            },
+            (Instruction::Phx, _) => {
+                self.push_cycle(self.processor.rx);
+                return true;
+            },
+            (Instruction::Phy, _) => {
+                self.push_cycle(self.processor.ry);
+                return true;
+            },
+            (_, 3) => self.stack_dummy_read(),
+            (Instruction::Pla, _) => {
+                self.processor.acc = self.pull_cycle();
+                self.processor.set_zn(self.processor.acc);
+                return true;
+            },
+            (Instruction::Plp, _) => {
+                let status = self.pull_cycle();
+                self.processor.set_pulled_status(status);
+                return true;
+            },
+            (Instruction::Plx, _) => {
+                self.processor.rx = self.pull_cycle();
+                self.processor.set_zn(self.processor.rx);
+                return true;
+            },
+            (Instruction::Ply, _) => {
+                self.processor.ry = self.pull_cycle();
+                self.processor.set_zn(self.processor.ry);
+                return true;
+            },
+            (Instruction::Rti, 4) => {
+                let status = self.pull_cycle();
+                self.processor.set_pulled_status(status);
+            },
+            (Instruction::Rti, 5) | (Instruction::Rts, 4) => {
+                self.cycle.address = self.pull_cycle() as u16;
+            },
+            (Instruction::Rti, _) => {
+                let high = self.pull_cycle();
+                self.processor.pc = (high as u16) << 8 | self.cycle.address;
+                return true;
+            },
+            (Instruction::Rts, 5) => {
+                let high = self.pull_cycle();
+                self.processor.pc = (high as u16) << 8 | self.cycle.address;
+            },
+            (Instruction::Rts, _) => {
+                // The pulled address is the last byte of the jsr, step over it
+                self.fetch_operand();
+                return true;
+            },
+            (instruction, _) => unreachable!("{:?} is not a control instruction", instruction),
+        }
+        false
+    }
+
+    /// Cycles 2 to 7 of IRQ and NMI, and 3 to 7 of BRK
+    fn interrupt_cycle(&mut self) -> bool {
+        let pc = self.processor.pc;
+        match self.cycle.cycle {
+            2 => {
+                self.read(pc);
+            },
+            3 => self.push_cycle((pc >> 8) as u8),
+            4 => self.push_cycle(pc as u8),
+            5 => {
+                // An NMI arriving now hijacks an IRQ or BRK, its vector is used instead
+                if self.cycle.vector == IRQ_VECTOR && self.interrupts.take_nmi() {
+                    self.cycle.vector = NMI_VECTOR;
+                }
+                let break_flag = self.cycle.sequence == Sequence::Instruction;
+                self.push_cycle(self.processor.pushed_status(break_flag));
+            },
+            6 => {
+                self.cycle.address = self.read(self.cycle.vector) as u16;
+                self.processor.set_interrupt(true);
+                if self.cpu.cmos() {
+                    self.processor.set_decimal(false);
+                }
+            },
+            _ => {
+                let high = self.read(self.cycle.vector.wrapping_add(1));
+                self.processor.pc = (high as u16) << 8 | self.cycle.address;
+                return true;
+            },
+        }
+        false
+    }
+
+    /// The reset goes through the cycles of an interrupt with the stack
+    /// writes turned into reads
+    fn reset_cycle(&mut self) -> bool {
+        match self.cycle.cycle {
+            2 => {
+                self.read(self.processor.pc);
+            },
+            3..=5 => {
+                self.stack_dummy_read();
+                self.processor.sp = self.processor.sp.wrapping_sub(1);
+            },
+            6 => {
+                self.cycle.address = self.read(RESET_VECTOR) as u16;
+                self.processor.set_interrupt(true);
+                if self.cpu.cmos() {
+                    self.processor.set_decimal(false);
+                }
+            },
+            _ => {
+                let high = self.read(RESET_VECTOR.wrapping_add(1));
+                self.processor.pc = (high as u16) << 8 | self.cycle.address;
+                self.processor.halted = false;
+                self.cpu.reset();
+                self.bus.reset();
+                self.add_info(format!("Reset, starting at: {}", self.symbols.label(self.processor.pc)));
+                return true;
+            },
+        }
+        false
+    }
+}
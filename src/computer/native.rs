@@ -0,0 +1,897 @@
+//! The 65C816 core.
+//!
+//! The 65C816 runs an instruction at a time on its own core: its accumulator
+//! and index registers switch between 8 and 16 bits, the direct page and the
+//! stack can be anywhere in bank 0 and addresses are 24 bits wide. It starts
+//! in emulation mode, where it runs 6502 code like a 65C02 without the
+//! Rockwell bit instructions. XCE switches to native mode and back.
+
+use crate::addressing::AddressingMode as Mode;
+use crate::bus::Bus;
+use crate::instructions::Instruction;
+use crate::interrupts::{IRQ_VECTOR, NMI_VECTOR};
+
+use super::{BusCycle, Computer, BREAK, INTERRUPT_CYCLES, UNUSED};
+
+/// The M flag in native mode: 8-bit accumulator and memory operands
+pub const MEMORY_8: u8 = 0b00100000;
+/// The X flag in native mode: 8-bit index registers
+pub const INDEX_8: u8 = 0b00010000;
+
+/// COP handler in emulation mode, the other vectors are the 6502 ones
+const COP_VECTOR: u16 = 0xfff4;
+// Native mode vectors
+const NATIVE_COP_VECTOR: u16 = 0xffe4;
+const NATIVE_BRK_VECTOR: u16 = 0xffe6;
+const NATIVE_NMI_VECTOR: u16 = 0xffea;
+const NATIVE_IRQ_VECTOR: u16 = 0xffee;
+
+/// Where an operand is. Direct page and stack operands stay in bank 0,
+/// their second byte wraps around at $FFFF. The others are 24-bit addresses.
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    address: u32,
+    bank0: bool,
+}
+
+impl Location {
+    fn bank0(address: u16) -> Location {
+        Location { address: address as u32, bank0: true }
+    }
+
+    fn long(address: u32) -> Location {
+        Location { address: address & 0xff_ffff, bank0: false }
+    }
+
+    /// Address of the next byte of the operand
+    fn next(self) -> u32 {
+        if self.bank0 {
+            (self.address as u16).wrapping_add(1) as u32
+        } else {
+            (self.address + 1) & 0xff_ffff
+        }
+    }
+}
+
+impl<B: Bus> Computer<B> {
+    pub(super) fn run_native_instruction(&mut self) {
+        if !self.awake() {
+            return;
+        }
+        if self.service_native_interrupts() {
+            return;
+        }
+
+        let pc = self.processor.pc;
+        let interrupt_flag = self.processor.interrupt();
+        let opcode = self.fetch();
+        self.processor.clock += self.cpu.cycles(opcode) as u64;
+        let (instruction, mode) = self.cpu.decode(opcode);
+        self.add_info(format!("Running instruction {}: {:#x} at: {:#04x}:{:#06x}", instruction.name(), opcode, self.processor.pbr, pc));
+        self.execute_native(instruction, mode);
+        self.update_irq_mask(instruction, interrupt_flag);
+    }
+
+    fn execute_native(&mut self, instruction: Instruction, mode: Mode) {
+        match instruction {
+            Instruction::Adc | Instruction::And | Instruction::Bit | Instruction::Cmp
+            | Instruction::Eor | Instruction::Lda | Instruction::Ora | Instruction::Sbc => {
+                let wide = !self.m8();
+                let val = self.read_operand_native(mode, wide);
+                self.accumulator_op(instruction, mode, val);
+            },
+            Instruction::Cpx | Instruction::Cpy | Instruction::Ldx | Instruction::Ldy => {
+                let wide = !self.x8();
+                let val = self.read_operand_native(mode, wide);
+                match instruction {
+                    Instruction::Cpx => self.compare_native(self.x(), val, wide),
+                    Instruction::Cpy => self.compare_native(self.y(), val, wide),
+                    Instruction::Ldx => { self.set_x(val); self.set_nz(val, wide) },
+                    _ => { self.set_y(val); self.set_nz(val, wide) },
+                }
+            },
+            Instruction::Sta | Instruction::Stz => {
+                let wide = !self.m8();
+                let val = if instruction == Instruction::Sta { self.a() } else { 0 };
+                let location = self.locate(mode).0;
+                self.memory_cycles(wide, 1);
+                self.write_data(location, val, wide);
+            },
+            Instruction::Stx | Instruction::Sty => {
+                let wide = !self.x8();
+                let val = if instruction == Instruction::Stx { self.x() } else { self.y() };
+                let location = self.locate(mode).0;
+                self.memory_cycles(wide, 1);
+                self.write_data(location, val, wide);
+            },
+            Instruction::Asl | Instruction::Dec | Instruction::Inc | Instruction::Lsr
+            | Instruction::Rol | Instruction::Ror | Instruction::Trb | Instruction::Tsb => {
+                let wide = !self.m8();
+                if mode == Mode::Accumulator {
+                    let result = self.modify_native(instruction, self.a(), wide);
+                    self.set_a(result);
+                } else {
+                    let location = self.locate(mode).0;
+                    self.memory_cycles(wide, 2);
+                    let val = self.read_data(location, wide);
+                    let result = self.modify_native(instruction, val, wide);
+                    self.write_data(location, result, wide);
+                }
+            },
+            Instruction::Bcc | Instruction::Bcs | Instruction::Beq | Instruction::Bmi
+            | Instruction::Bne | Instruction::Bpl | Instruction::Bvc | Instruction::Bvs
+            | Instruction::Bra => {
+                let offset = self.fetch() as i8;
+                if self.branch_taken(instruction, 0) {
+                    let pc = self.processor.pc;
+                    let target = pc.wrapping_add(offset as u16);
+                    self.processor.clock += 1;
+                    if self.processor.emulation && pc & 0xff00 != target & 0xff00 {
+                        self.processor.clock += 1;
+                    }
+                    self.processor.pc = target;
+                }
+            },
+            Instruction::Brl => {
+                let offset = self.fetch_word();
+                self.processor.pc = self.processor.pc.wrapping_add(offset);
+            },
+            Instruction::Jmp => self.jmp_native(mode),
+            Instruction::Jsr => {
+                let target = match mode {
+                    Mode::AbsoluteIndirectX => {
+                        let pointer = self.fetch_word().wrapping_add(self.x());
+                        self.program_word(pointer)
+                    },
+                    _ => self.fetch_word(),
+                };
+                // The return address pushed is the last byte of the JSR
+                self.push_word_native(self.processor.pc.wrapping_sub(1));
+                self.processor.pc = target;
+            },
+            Instruction::Jsl => {
+                let target = self.fetch_long();
+                self.push_native(self.processor.pbr);
+                self.push_word_native(self.processor.pc.wrapping_sub(1));
+                self.jump_long(target);
+            },
+            Instruction::Rts => {
+                self.processor.pc = self.pull_word_native().wrapping_add(1);
+            },
+            Instruction::Rtl => {
+                self.processor.pc = self.pull_word_native().wrapping_add(1);
+                self.processor.pbr = self.pull_native();
+            },
+            Instruction::Rti => {
+                let status = self.pull_native();
+                self.set_status(status);
+                self.processor.pc = self.pull_word_native();
+                if !self.processor.emulation {
+                    self.processor.pbr = self.pull_native();
+                    self.processor.clock += 1;
+                }
+            },
+            Instruction::Brk | Instruction::Cop => {
+                // Skip the signature byte
+                self.fetch();
+                let (vector, native_vector) = if instruction == Instruction::Brk {
+                    (IRQ_VECTOR, NATIVE_BRK_VECTOR)
+                } else {
+                    (COP_VECTOR, NATIVE_COP_VECTOR)
+                };
+                self.interrupt_native(instruction == Instruction::Brk, vector, native_vector);
+            },
+            Instruction::Pha => {
+                let wide = !self.m8();
+                self.push_register(self.a(), wide);
+            },
+            Instruction::Phx => {
+                let wide = !self.x8();
+                self.push_register(self.x(), wide);
+            },
+            Instruction::Phy => {
+                let wide = !self.x8();
+                self.push_register(self.y(), wide);
+            },
+            Instruction::Pla => {
+                let wide = !self.m8();
+                let val = self.pull_register(wide);
+                self.set_a(val);
+                self.set_nz(val, wide);
+            },
+            Instruction::Plx => {
+                let wide = !self.x8();
+                let val = self.pull_register(wide);
+                self.set_x(val);
+                self.set_nz(val, wide);
+            },
+            Instruction::Ply => {
+                let wide = !self.x8();
+                let val = self.pull_register(wide);
+                self.set_y(val);
+                self.set_nz(val, wide);
+            },
+            Instruction::Php => {
+                let status = if self.processor.emulation {
+                    self.processor.pushed_status(true)
+                } else {
+                    self.processor.flags
+                };
+                self.push_native(status);
+            },
+            Instruction::Plp => {
+                let status = self.pull_native();
+                self.set_status(status);
+            },
+            Instruction::Phb => self.push_native(self.processor.dbr),
+            Instruction::Phk => self.push_native(self.processor.pbr),
+            Instruction::Phd => self.push_word_native(self.processor.dp),
+            Instruction::Plb => {
+                self.processor.dbr = self.pull_native();
+                self.processor.set_zn(self.processor.dbr);
+            },
+            Instruction::Pld => {
+                self.processor.dp = self.pull_word_native();
+                self.set_nz(self.processor.dp, true);
+            },
+            Instruction::Pea => {
+                let val = self.fetch_word();
+                self.push_word_native(val);
+            },
+            Instruction::Pei => {
+                let offset = self.fetch() as u16;
+                self.direct_page_cycles();
+                let val = self.direct_word(offset);
+                self.push_word_native(val);
+            },
+            Instruction::Per => {
+                let offset = self.fetch_word();
+                self.push_word_native(self.processor.pc.wrapping_add(offset));
+            },
+            Instruction::Mvn | Instruction::Mvp => self.block_move(instruction),
+            Instruction::Rep | Instruction::Sep => {
+                let bits = self.fetch();
+                if instruction == Instruction::Rep {
+                    self.set_status(self.processor.flags & !bits);
+                } else {
+                    self.set_status(self.processor.flags | bits);
+                }
+            },
+            Instruction::Xce => self.exchange_carry_emulation(),
+            Instruction::Xba => {
+                let p = &mut self.processor;
+                std::mem::swap(&mut p.acc, &mut p.acc_high);
+                p.set_zn(p.acc);
+            },
+            Instruction::Wdm => {
+                // Reserved for future use, skips its operand
+                self.fetch();
+            },
+            Instruction::Wai => {
+                self.add_info(format!("Waiting for an interrupt at: {}", self.symbols.label(self.processor.pc)));
+                self.processor.waiting = true;
+            },
+            Instruction::Stp => {
+                self.processor.pc = self.processor.pc.wrapping_sub(1);
+                self.halt(instruction);
+            },
+            Instruction::Clc | Instruction::Cld | Instruction::Cli | Instruction::Clv
+            | Instruction::Sec | Instruction::Sed | Instruction::Sei | Instruction::Nop => {
+                self.implied_op(instruction);
+            },
+            _ => self.transfer(instruction),
+        }
+    }
+
+    /// The register transfers and the index increments and decrements,
+    /// as wide as their destination
+    fn transfer(&mut self, instruction: Instruction) {
+        let m8 = self.m8();
+        let x8 = self.x8();
+        match instruction {
+            Instruction::Tax => { self.set_x(self.c()); self.set_nz(self.x(), !x8) },
+            Instruction::Tay => { self.set_y(self.c()); self.set_nz(self.y(), !x8) },
+            Instruction::Txa => { self.set_a(self.x()); self.set_nz(self.a(), !m8) },
+            Instruction::Tya => { self.set_a(self.y()); self.set_nz(self.a(), !m8) },
+            Instruction::Txy => { self.set_y(self.x()); self.set_nz(self.y(), !x8) },
+            Instruction::Tyx => { self.set_x(self.y()); self.set_nz(self.x(), !x8) },
+            Instruction::Tsx => { self.set_x(self.s()); self.set_nz(self.x(), !x8) },
+            // Like TCS, these leave the flags alone
+            Instruction::Txs => self.set_s(self.x()),
+            Instruction::Tcs => self.set_s(self.c()),
+            // The transfers of the 16-bit registers are always 16-bit
+            Instruction::Tsc => { self.set_c(self.s()); self.set_nz(self.c(), true) },
+            Instruction::Tcd => { self.processor.dp = self.c(); self.set_nz(self.c(), true) },
+            Instruction::Tdc => { self.set_c(self.processor.dp); self.set_nz(self.c(), true) },
+            Instruction::Inx => { self.set_x(self.x().wrapping_add(1)); self.set_nz(self.x(), !x8) },
+            Instruction::Iny => { self.set_y(self.y().wrapping_add(1)); self.set_nz(self.y(), !x8) },
+            Instruction::Dex => { self.set_x(self.x().wrapping_sub(1)); self.set_nz(self.x(), !x8) },
+            Instruction::Dey => { self.set_y(self.y().wrapping_sub(1)); self.set_nz(self.y(), !x8) },
+            _ => unreachable!("{:?} is not a 65C816 instruction", instruction),
+        }
+    }
+
+    /// Run the reset, NMI or IRQ sequence if one is due.
+    /// Returns whether one was run instead of the next instruction.
+    fn service_native_interrupts(&mut self) -> bool {
+        if self.interrupts.take_reset() {
+            self.reset_native();
+            return true;
+        }
+
+        if self.interrupts.take_nmi() {
+            self.add_info(format!("Servicing NMI at: {}", self.symbols.label(self.processor.pc)));
+            self.interrupt_native(false, NMI_VECTOR, NATIVE_NMI_VECTOR);
+            self.processor.clock += INTERRUPT_CYCLES;
+            return true;
+        }
+
+        if self.interrupts.irq() && !self.irq_masked {
+            self.add_info(format!("Servicing IRQ at: {}", self.symbols.label(self.processor.pc)));
+            self.interrupt_native(false, IRQ_VECTOR, NATIVE_IRQ_VECTOR);
+            self.processor.clock += INTERRUPT_CYCLES;
+            return true;
+        }
+
+        false
+    }
+
+    /// The reset sequence, which goes back to emulation mode with the
+    /// direct page and the banks at 0
+    fn reset_native(&mut self) {
+        let p = &mut self.processor;
+        p.emulation = true;
+        p.flags = (p.flags | UNUSED) & !BREAK;
+        p.rx_high = 0;
+        p.ry_high = 0;
+        p.sp_high = 1;
+        p.dp = 0;
+        p.dbr = 0;
+        p.pbr = 0;
+        self.reset();
+    }
+
+    /// Push the return address and the status register, then jump through
+    /// the vector of the current mode. Native mode pushes the program bank first.
+    fn interrupt_native(&mut self, break_flag: bool, vector: u16, native_vector: u16) {
+        let return_address = self.processor.pc;
+        let vector = if self.processor.emulation {
+            self.push_word_native(return_address);
+            self.push_native(self.processor.pushed_status(break_flag));
+            vector
+        } else {
+            self.push_native(self.processor.pbr);
+            self.push_word_native(return_address);
+            self.push_native(self.processor.flags);
+            self.processor.clock += 1;
+            native_vector
+        };
+        self.processor.set_interrupt(true);
+        self.processor.set_decimal(false);
+        self.irq_masked = true;
+        self.processor.pbr = 0;
+        self.processor.pc = self.read_word_long(Location::bank0(vector));
+    }
+
+    /// Load the status register. In native mode M and X are real flags, and
+    /// 8-bit index registers lose their high byte.
+    fn set_status(&mut self, status: u8) {
+        if self.processor.emulation {
+            self.processor.set_pulled_status(status);
+            return;
+        }
+        self.processor.flags = status;
+        if status & INDEX_8 != 0 {
+            self.processor.rx_high = 0;
+            self.processor.ry_high = 0;
+        }
+    }
+
+    /// XCE: swap the carry and emulation bits. Emulation mode has 8-bit
+    /// registers and the stack in page $01, native mode starts the same way.
+    fn exchange_carry_emulation(&mut self) {
+        let p = &mut self.processor;
+        let emulation = p.carry();
+        p.set_carry(p.emulation);
+        p.emulation = emulation;
+        if emulation {
+            p.flags = (p.flags | UNUSED) & !BREAK;
+            p.rx_high = 0;
+            p.ry_high = 0;
+            p.sp_high = 1;
+        } else {
+            p.flags |= MEMORY_8 | INDEX_8;
+        }
+        self.add_info(format!("Switched to {} mode", if emulation { "emulation" } else { "native" }));
+    }
+
+    fn jmp_native(&mut self, mode: Mode) {
+        match mode {
+            Mode::Absolute => self.processor.pc = self.fetch_word(),
+            Mode::AbsoluteLong => {
+                let target = self.fetch_long();
+                self.jump_long(target);
+            },
+            // The pointer is in bank 0
+            Mode::Indirect => {
+                let pointer = self.fetch_word();
+                self.processor.pc = self.read_word_long(Location::bank0(pointer));
+            },
+            Mode::AbsoluteIndirectLong => {
+                let pointer = self.fetch_word();
+                let target = self.read_long_pointer(Location::bank0(pointer));
+                self.jump_long(target);
+            },
+            // The pointer is in the program bank
+            Mode::AbsoluteIndirectX => {
+                let pointer = self.fetch_word().wrapping_add(self.x());
+                self.processor.pc = self.program_word(pointer);
+            },
+            _ => unreachable!("JMP does not use {:?}", mode),
+        }
+    }
+
+    fn jump_long(&mut self, target: u32) {
+        self.processor.pc = target as u16;
+        self.processor.pbr = (target >> 16) as u8;
+    }
+
+    /// MVN and MVP move a byte from the source bank at X to the destination
+    /// bank at Y, and run again until C goes past 0
+    fn block_move(&mut self, instruction: Instruction) {
+        let destination = self.fetch() as u32;
+        let source = self.fetch() as u32;
+        self.processor.dbr = destination as u8;
+        let val = self.read_long(source << 16 | self.x() as u32);
+        self.write_long(destination << 16 | self.y() as u32, val);
+        if instruction == Instruction::Mvn {
+            self.set_x(self.x().wrapping_add(1));
+            self.set_y(self.y().wrapping_add(1));
+        } else {
+            self.set_x(self.x().wrapping_sub(1));
+            self.set_y(self.y().wrapping_sub(1));
+        }
+        let count = self.c().wrapping_sub(1);
+        self.set_c(count);
+        if count != 0xffff {
+            self.processor.pc = self.processor.pc.wrapping_sub(3);
+        }
+    }
+
+    /// ADC, AND, BIT, CMP, EOR, LDA, ORA and SBC, with an operand as wide as the accumulator
+    fn accumulator_op(&mut self, instruction: Instruction, mode: Mode, val: u16) {
+        let wide = !self.m8();
+        let a = self.a();
+        match instruction {
+            Instruction::Adc => self.adc_native(val),
+            Instruction::Sbc => self.sbc_native(val),
+            Instruction::Cmp => self.compare_native(a, val, wide),
+            Instruction::And => { self.set_a(a & val); self.set_nz(a & val, wide) },
+            Instruction::Ora => { self.set_a(a | val); self.set_nz(a | val, wide) },
+            Instruction::Eor => { self.set_a(a ^ val); self.set_nz(a ^ val, wide) },
+            Instruction::Lda => { self.set_a(val); self.set_nz(val, wide) },
+            Instruction::Bit => {
+                self.processor.set_zero(a & val == 0);
+                // BIT immediate only sets Z
+                if mode != Mode::Immediate {
+                    let sign = if wide { 0x8000 } else { 0x80 };
+                    self.processor.set_negative(val & sign != 0);
+                    self.processor.set_overflow(val & (sign >> 1) != 0);
+                }
+            },
+            _ => unreachable!("{:?} does not read the accumulator", instruction),
+        }
+    }
+
+    fn modify_native(&mut self, instruction: Instruction, val: u16, wide: bool) -> u16 {
+        let sign = if wide { 0x8000 } else { 0x80 };
+        let mask = if wide { 0xffff } else { 0xff };
+        let carry = self.processor.carry();
+        let result = match instruction {
+            Instruction::Asl => {
+                self.processor.set_carry(val & sign != 0);
+                val << 1 & mask
+            },
+            Instruction::Lsr => {
+                self.processor.set_carry(val & 1 != 0);
+                val >> 1
+            },
+            Instruction::Rol => {
+                self.processor.set_carry(val & sign != 0);
+                (val << 1 | carry as u16) & mask
+            },
+            Instruction::Ror => {
+                self.processor.set_carry(val & 1 != 0);
+                val >> 1 | if carry { sign } else { 0 }
+            },
+            Instruction::Inc => val.wrapping_add(1) & mask,
+            Instruction::Dec => val.wrapping_sub(1) & mask,
+            // TSB and TRB set Z from the bits the accumulator and memory share
+            Instruction::Tsb | Instruction::Trb => {
+                let a = self.a();
+                self.processor.set_zero(a & val == 0);
+                return if instruction == Instruction::Tsb { val | a } else { val & !a };
+            },
+            _ => unreachable!("{:?} does not modify its operand", instruction),
+        };
+        self.set_nz(result, wide);
+        result
+    }
+
+    fn compare_native(&mut self, register: u16, val: u16, wide: bool) {
+        self.processor.set_carry(register >= val);
+        self.set_nz(register.wrapping_sub(val), wide);
+    }
+
+    /// 8-bit ADC is the 65C02 one
+    fn adc_native(&mut self, val: u16) {
+        if self.m8() {
+            self.adc(val as u8);
+            return;
+        }
+        let a = self.c();
+        let carry = self.processor.carry();
+        let (result, carry, overflow) = if self.processor.decimal() {
+            add_decimal_16(a, val, carry)
+        } else {
+            let sum = a as u32 + val as u32 + carry as u32;
+            let result = sum as u16;
+            (result, sum > 0xffff, (a ^ result) & (val ^ result) & 0x8000 != 0)
+        };
+        self.processor.set_carry(carry);
+        self.processor.set_overflow(overflow);
+        self.set_nz(result, true);
+        self.set_c(result);
+    }
+
+    /// 8-bit SBC is the 65C02 one. In decimal mode the flags other than N
+    /// and Z come from the binary subtraction.
+    fn sbc_native(&mut self, val: u16) {
+        if self.m8() {
+            self.sbc(val as u8);
+            return;
+        }
+        let a = self.c();
+        let carry = self.processor.carry();
+        let difference = a as u32 + !val as u32 + carry as u32;
+        let mut result = difference as u16;
+        self.processor.set_carry(difference > 0xffff);
+        self.processor.set_overflow((a ^ result) & (!val ^ result) & 0x8000 != 0);
+        if self.processor.decimal() {
+            result = subtract_decimal_16(a, val, carry);
+        }
+        self.set_nz(result, true);
+        self.set_c(result);
+    }
+
+    /// Read the operand of an instruction that only reads it
+    fn read_operand_native(&mut self, mode: Mode, wide: bool) -> u16 {
+        if mode == Mode::Immediate {
+            self.processor.clock += wide as u64;
+            return if wide { self.fetch_word() } else { self.fetch() as u16 };
+        }
+        let (location, page_crossed) = self.locate(mode);
+        let indexed = mode == Mode::AbsoluteX || mode == Mode::AbsoluteY || mode == Mode::IndirectY;
+        if indexed && (page_crossed || !self.x8()) {
+            self.processor.clock += 1;
+        }
+        self.memory_cycles(wide, 1);
+        self.read_data(location, wide)
+    }
+
+    /// Fetch the operand bytes of `mode` and work out where the operand is,
+    /// and whether indexing crossed a page
+    fn locate(&mut self, mode: Mode) -> (Location, bool) {
+        let dbr = (self.processor.dbr as u32) << 16;
+        match mode {
+            Mode::ZeroPage | Mode::ZeroPageX | Mode::ZeroPageY | Mode::ZeroPageIndirect
+            | Mode::IndirectX | Mode::IndirectY | Mode::ZeroPageIndirectLong
+            | Mode::ZeroPageIndirectLongY => {
+                let offset = self.fetch() as u16;
+                self.direct_page_cycles();
+                match mode {
+                    Mode::ZeroPage => (Location::bank0(self.direct(offset)), false),
+                    Mode::ZeroPageX => (Location::bank0(self.direct(offset.wrapping_add(self.x()))), false),
+                    Mode::ZeroPageY => (Location::bank0(self.direct(offset.wrapping_add(self.y()))), false),
+                    Mode::ZeroPageIndirect => (Location::long(dbr | self.direct_word(offset) as u32), false),
+                    Mode::IndirectX => {
+                        let pointer = self.direct_word(offset.wrapping_add(self.x()));
+                        (Location::long(dbr | pointer as u32), false)
+                    },
+                    Mode::IndirectY => {
+                        let base = dbr | self.direct_word(offset) as u32;
+                        Self::indexed(base, self.y())
+                    },
+                    Mode::ZeroPageIndirectLong => {
+                        let pointer = self.read_long_pointer(Location::bank0(self.direct(offset)));
+                        (Location::long(pointer), false)
+                    },
+                    _ => {
+                        let pointer = self.read_long_pointer(Location::bank0(self.direct(offset)));
+                        (Location::long(pointer + self.y() as u32), false)
+                    },
+                }
+            },
+            Mode::Absolute => (Location::long(dbr | self.fetch_word() as u32), false),
+            Mode::AbsoluteX => {
+                let base = dbr | self.fetch_word() as u32;
+                Self::indexed(base, self.x())
+            },
+            Mode::AbsoluteY => {
+                let base = dbr | self.fetch_word() as u32;
+                Self::indexed(base, self.y())
+            },
+            Mode::AbsoluteLong => (Location::long(self.fetch_long()), false),
+            Mode::AbsoluteLongX => (Location::long(self.fetch_long() + self.x() as u32), false),
+            Mode::StackRelative => {
+                let offset = self.fetch() as u16;
+                (Location::bank0(self.s().wrapping_add(offset)), false)
+            },
+            Mode::StackRelativeIndirectY => {
+                let offset = self.fetch() as u16;
+                let pointer = self.read_word_long(Location::bank0(self.s().wrapping_add(offset)));
+                (Location::long((dbr | pointer as u32) + self.y() as u32), false)
+            },
+            _ => unreachable!("{:?} does not address memory", mode),
+        }
+    }
+
+    fn indexed(base: u32, index: u16) -> (Location, bool) {
+        let address = base + index as u32;
+        (Location::long(address), base & 0xff00 != address & 0xff00)
+    }
+
+    /// Address in bank 0 of `offset` in the direct page. In emulation mode,
+    /// a direct page on a page boundary wraps around like the zero page.
+    fn direct(&self, offset: u16) -> u16 {
+        let dp = self.processor.dp;
+        if self.processor.emulation && dp & 0xff == 0 {
+            dp | offset & 0xff
+        } else {
+            dp.wrapping_add(offset)
+        }
+    }
+
+    /// Pointer at `offset` in the direct page
+    fn direct_word(&mut self, offset: u16) -> u16 {
+        let low_byte = self.read_long(self.direct(offset) as u32) as u16;
+        let high_byte = self.read_long(self.direct(offset.wrapping_add(1)) as u32) as u16;
+        low_byte | high_byte << 8
+    }
+
+    /// Direct page addressing takes one more cycle when D is not on a page boundary
+    fn direct_page_cycles(&mut self) {
+        if self.processor.dp & 0xff != 0 {
+            self.processor.clock += 1;
+        }
+    }
+
+    /// 16-bit operands take `per_byte` more cycles, one for each access
+    fn memory_cycles(&mut self, wide: bool, per_byte: u64) {
+        if wide {
+            self.processor.clock += per_byte;
+        }
+    }
+
+    fn read_data(&mut self, location: Location, wide: bool) -> u16 {
+        let low_byte = self.read_long(location.address) as u16;
+        if !wide {
+            return low_byte;
+        }
+        low_byte | (self.read_long(location.next()) as u16) << 8
+    }
+
+    fn write_data(&mut self, location: Location, val: u16, wide: bool) {
+        self.write_long(location.address, val as u8);
+        if wide {
+            self.write_long(location.next(), (val >> 8) as u8);
+        }
+    }
+
+    fn read_word_long(&mut self, location: Location) -> u16 {
+        self.read_data(location, true)
+    }
+
+    fn read_long_pointer(&mut self, location: Location) -> u32 {
+        let word = self.read_word_long(location) as u32;
+        let bank = self.read_long(Location { address: location.next(), ..location }.next()) as u32;
+        bank << 16 | word
+    }
+
+    /// Word in the program bank, the pointer wraps around within the bank
+    fn program_word(&mut self, pointer: u16) -> u16 {
+        let bank = (self.processor.pbr as u32) << 16;
+        let low_byte = self.read_long(bank | pointer as u32) as u16;
+        let high_byte = self.read_long(bank | pointer.wrapping_add(1) as u32) as u16;
+        low_byte | high_byte << 8
+    }
+
+    /// Read a byte from the 24-bit bus. Bank 0 goes through the 6502 bus,
+    /// with its on-chip registers and interrupt port.
+    fn read_long(&mut self, address: u32) -> u8 {
+        if address <= 0xffff {
+            return self.read(address as u16);
+        }
+        let value = self.bus.read(address);
+        self.processor.bus = Some(BusCycle { address, value, write: false });
+        value
+    }
+
+    fn write_long(&mut self, address: u32, value: u8) {
+        if address <= 0xffff {
+            self.write(address as u16, value);
+            return;
+        }
+        self.bus.write(address, value);
+        self.processor.bus = Some(BusCycle { address, value, write: true });
+    }
+
+    /// Next byte of the instruction, PC wraps around within the program bank
+    fn fetch(&mut self) -> u8 {
+        let address = (self.processor.pbr as u32) << 16 | self.processor.pc as u32;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
+        self.read_long(address)
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let low_byte = self.fetch() as u16;
+        low_byte | (self.fetch() as u16) << 8
+    }
+
+    fn fetch_long(&mut self) -> u32 {
+        let word = self.fetch_word() as u32;
+        word | (self.fetch() as u32) << 16
+    }
+
+    /// Push a byte. In emulation mode the stack stays in page $01.
+    fn push_native(&mut self, val: u8) {
+        let s = self.s();
+        self.write_long(s as u32, val);
+        self.set_s(s.wrapping_sub(1));
+    }
+
+    fn pull_native(&mut self) -> u8 {
+        let s = self.s();
+        self.set_s(s.wrapping_add(1));
+        self.read_long(self.s() as u32)
+    }
+
+    fn push_word_native(&mut self, val: u16) {
+        self.push_native((val >> 8) as u8);
+        self.push_native(val as u8);
+    }
+
+    fn pull_word_native(&mut self) -> u16 {
+        let low_byte = self.pull_native() as u16;
+        low_byte | (self.pull_native() as u16) << 8
+    }
+
+    /// PHA, PHX and PHY, 16-bit registers take one more cycle
+    fn push_register(&mut self, val: u16, wide: bool) {
+        self.memory_cycles(wide, 1);
+        if wide {
+            self.push_word_native(val);
+        } else {
+            self.push_native(val as u8);
+        }
+    }
+
+    fn pull_register(&mut self, wide: bool) -> u16 {
+        self.memory_cycles(wide, 1);
+        if wide {
+            self.pull_word_native()
+        } else {
+            self.pull_native() as u16
+        }
+    }
+
+    fn m8(&self) -> bool {
+        self.processor.emulation || self.processor.flags & MEMORY_8 != 0
+    }
+
+    fn x8(&self) -> bool {
+        self.processor.emulation || self.processor.flags & INDEX_8 != 0
+    }
+
+    fn set_nz(&mut self, val: u16, wide: bool) {
+        if wide {
+            self.processor.set_zero(val == 0);
+            self.processor.set_negative(val & 0x8000 != 0);
+        } else {
+            self.processor.set_zn(val as u8);
+        }
+    }
+
+    /// The whole 16-bit accumulator
+    fn c(&self) -> u16 {
+        (self.processor.acc_high as u16) << 8 | self.processor.acc as u16
+    }
+
+    fn set_c(&mut self, val: u16) {
+        self.processor.acc = val as u8;
+        self.processor.acc_high = (val >> 8) as u8;
+    }
+
+    /// The accumulator as wide as M says, B is left alone in 8-bit mode
+    fn a(&self) -> u16 {
+        if self.m8() { self.processor.acc as u16 } else { self.c() }
+    }
+
+    fn set_a(&mut self, val: u16) {
+        if self.m8() {
+            self.processor.acc = val as u8;
+        } else {
+            self.set_c(val);
+        }
+    }
+
+    fn x(&self) -> u16 {
+        (self.processor.rx_high as u16) << 8 | self.processor.rx as u16
+    }
+
+    fn set_x(&mut self, val: u16) {
+        self.processor.rx = val as u8;
+        if !self.x8() {
+            self.processor.rx_high = (val >> 8) as u8;
+        }
+    }
+
+    fn y(&self) -> u16 {
+        (self.processor.ry_high as u16) << 8 | self.processor.ry as u16
+    }
+
+    fn set_y(&mut self, val: u16) {
+        self.processor.ry = val as u8;
+        if !self.x8() {
+            self.processor.ry_high = (val >> 8) as u8;
+        }
+    }
+
+    fn s(&self) -> u16 {
+        (self.processor.sp_high as u16) << 8 | self.processor.sp as u16
+    }
+
+    /// Set the stack pointer, its high byte stays $01 in emulation mode
+    fn set_s(&mut self, val: u16) {
+        self.processor.sp = val as u8;
+        if !self.processor.emulation {
+            self.processor.sp_high = (val >> 8) as u8;
+        }
+    }
+}
+
+/// 16-bit decimal addition, a digit at a time. V is set the way the 65C02
+/// does it, from the result before the top digit is adjusted.
+fn add_decimal_16(a: u16, b: u16, carry: bool) -> (u16, bool, bool) {
+    let mut result = 0;
+    let mut carry = carry as u16;
+    let mut overflow = false;
+    for shift in (0..16).step_by(4) {
+        let mut digit = (a >> shift & 0xf) + (b >> shift & 0xf) + carry;
+        if shift == 12 {
+            let binary = result | (digit & 0xf) << 12;
+            overflow = (a ^ binary) & (b ^ binary) & 0x8000 != 0;
+        }
+        if digit > 9 {
+            digit += 6;
+        }
+        carry = (digit > 0xf) as u16;
+        result |= (digit & 0xf) << shift;
+    }
+    (result, carry != 0, overflow)
+}
+
+/// 16-bit decimal subtraction, a digit at a time
+fn subtract_decimal_16(a: u16, b: u16, carry: bool) -> u16 {
+    let mut result = 0;
+    let mut borrow = !carry as i16;
+    for shift in (0..16).step_by(4) {
+        let mut digit = (a >> shift & 0xf) as i16 - (b >> shift & 0xf) as i16 - borrow;
+        borrow = (digit < 0) as i16;
+        if digit < 0 {
+            digit -= 6;
+        }
+        result |= ((digit & 0xf) as u16) << shift;
+    }
+    result
+}
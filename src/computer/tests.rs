@@ -0,0 +1,554 @@
+//! The NMOS addressing quirks, the wraparound of the address space, the
+//! timing, the interrupts and the buses the computer runs on, run on both
+//! cores, and the 65C816 core
+
+use std::sync::mpsc;
+
+use super::native::{INDEX_8, MEMORY_8};
+use super::{place_image, BusCycle, Computer, BREAK, CARRY, DECIMAL, INTERRUPT, MEMORY_SIZE, NEGATIVE, OVERFLOW, UNUSED, ZERO};
+use crate::bus::{Bus, Ram};
+use crate::device::{Device, Devices};
+use crate::symbols::Symbols;
+use crate::variant::Variant;
+
+/// A computer with `program` at $0200 and the other `bytes` poked in
+fn computer(variant: Variant, cycle_stepped: bool, program: &[u8], bytes: &[(u16, u8)]) -> Computer {
+    let mut data = vec![0; 0x10000];
+    data[0x0200..0x0200 + program.len()].copy_from_slice(program);
+    for &(address, value) in bytes {
+        data[address as usize] = value;
+    }
+    let (tx, _) = mpsc::channel();
+    let (_, rx) = mpsc::channel();
+    let mut computer = Computer::new(tx, rx, data);
+    computer.set_variant(variant);
+    computer.set_cycle_stepped(cycle_stepped);
+    computer.set_pc(0x0200);
+    computer
+}
+
+/// Run `count` instructions on both cores and check they agree
+fn run(variant: Variant, program: &[u8], bytes: &[(u16, u8)], count: usize) -> Computer {
+    run_with(variant, program, bytes, count, |_| {})
+}
+
+/// Run `count` instructions, or interrupt sequences, on both cores after
+/// `setup` and check they agree
+fn run_with(variant: Variant, program: &[u8], bytes: &[(u16, u8)], count: usize, setup: impl Fn(&mut Computer)) -> Computer {
+    let mut instruction_stepped = computer(variant, false, program, bytes);
+    let mut cycle_stepped = computer(variant, true, program, bytes);
+    setup(&mut instruction_stepped);
+    setup(&mut cycle_stepped);
+    for _ in 0..count {
+        instruction_stepped.next_instruction();
+        cycle_stepped.next_instruction();
+    }
+    let (a, b) = (&instruction_stepped.processor, &cycle_stepped.processor);
+    assert_eq!((a.pc, a.acc, a.rx, a.ry, a.sp, a.flags, a.clock), (b.pc, b.acc, b.rx, b.ry, b.sp, b.flags, b.clock));
+    instruction_stepped
+}
+
+#[test]
+fn read_word_wraps_at_ffff() {
+    let mut computer = computer(Variant::Nmos6502, false, &[], &[(0xffff, 0x34), (0x0000, 0x12)]);
+    assert_eq!(computer.read_word(0xffff), 0x1234);
+    assert_eq!(computer.processor.bus, Some(BusCycle { address: 0x0000, value: 0x12, write: false }));
+}
+
+#[test]
+fn nmos_jmp_indirect_page_wrap() {
+    // JMP ($10FF)
+    let bytes = [(0x10ff, 0x34), (0x1000, 0x12), (0x1100, 0x56)];
+    let computer = run(Variant::Nmos6502, &[0x6c, 0xff, 0x10], &bytes, 1);
+    assert_eq!(computer.processor.pc, 0x1234);
+    let computer = run(Variant::Cmos65c02, &[0x6c, 0xff, 0x10], &bytes, 1);
+    assert_eq!(computer.processor.pc, 0x5634);
+}
+
+#[test]
+fn zero_page_x_wraps() {
+    // LDX #$20, LDA $F0,X reads $10, not $110
+    let computer = run(Variant::Nmos6502, &[0xa2, 0x20, 0xb5, 0xf0], &[(0x0010, 0x42), (0x0110, 0x99)], 2);
+    assert_eq!(computer.processor.acc, 0x42);
+}
+
+#[test]
+fn zero_page_y_wraps() {
+    // LDY #$20, LDX $F0,Y
+    let computer = run(Variant::Nmos6502, &[0xa0, 0x20, 0xb6, 0xf0], &[(0x0010, 0x42), (0x0110, 0x99)], 2);
+    assert_eq!(computer.processor.rx, 0x42);
+}
+
+#[test]
+fn indirect_x_pointer_wraps() {
+    // LDX #$0F, LDA ($F0,X): the pointer is at $FF and $00
+    let bytes = [(0x00ff, 0x34), (0x0000, 0x12), (0x0100, 0x56), (0x1234, 0x42), (0x5634, 0x99)];
+    let computer = run(Variant::Nmos6502, &[0xa2, 0x0f, 0xa1, 0xf0], &bytes, 2);
+    assert_eq!(computer.processor.acc, 0x42);
+}
+
+#[test]
+fn indirect_y_pointer_wraps() {
+    // LDY #$01, LDA ($FF),Y
+    let bytes = [(0x00ff, 0x34), (0x0000, 0x12), (0x0100, 0x56), (0x1235, 0x42), (0x5635, 0x99)];
+    let computer = run(Variant::Nmos6502, &[0xa0, 0x01, 0xb1, 0xff], &bytes, 2);
+    assert_eq!(computer.processor.acc, 0x42);
+}
+
+#[test]
+fn zero_page_indirect_pointer_wraps() {
+    // LDA ($FF) on the 65C02
+    let bytes = [(0x00ff, 0x34), (0x0000, 0x12), (0x0100, 0x56), (0x1234, 0x42), (0x5634, 0x99)];
+    let computer = run(Variant::Cmos65c02, &[0xb2, 0xff], &bytes, 1);
+    assert_eq!(computer.processor.acc, 0x42);
+}
+
+#[test]
+fn pc_wraps_at_ffff() {
+    // JSR $1234 split across $FFFF and $0000
+    let bytes = [(0xfffe, 0xea), (0xffff, 0x20), (0x0000, 0x34), (0x0001, 0x12)];
+    let mut computer = computer(Variant::Nmos6502, false, &[], &bytes);
+    computer.set_pc(0xfffe);
+    computer.next_instruction();
+    computer.next_instruction();
+    assert_eq!(computer.processor.pc, 0x1234);
+    // The return address is the last byte of the JSR
+    assert_eq!((computer.peek(0x01fd), computer.peek(0x01fc)), (0x00, 0x01));
+}
+
+#[test]
+fn short_image_is_loaded_at_its_address() {
+    let mut data = vec![0; MEMORY_SIZE];
+    place_image(&mut data, &[0xa9, 0x42], 0xc000).unwrap();
+    assert_eq!(&data[0xc000..0xc002], &[0xa9, 0x42]);
+    assert!(place_image(&mut data, &[0xea; 3], 0xfffe).is_err());
+}
+
+/// RAM with a ROM above $C000 that counts the reads of $D000
+#[derive(Debug)]
+struct CountingBus {
+    ram: Ram,
+    reads: usize,
+}
+
+impl Bus for CountingBus {
+    fn read(&mut self, address: u32) -> u8 {
+        if address == 0xd000 {
+            self.reads += 1;
+        }
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        if address < 0xc000 {
+            self.ram.write(address, value);
+        }
+    }
+
+    fn peek(&self, address: u32) -> u8 {
+        self.ram.peek(address)
+    }
+}
+
+#[test]
+fn custom_bus() {
+    // LDA $D000, STA $C000, LDA $C000
+    let mut data = vec![0; 0x10000];
+    data[0x0200..0x0209].copy_from_slice(&[0xad, 0x00, 0xd0, 0x8d, 0x00, 0xc0, 0xad, 0x00, 0xc0]);
+    data[0xd000] = 0x42;
+    for &cycle_stepped in &[false, true] {
+        let bus = CountingBus { ram: Ram::new(data.clone()), reads: 0 };
+        let (tx, _) = mpsc::channel();
+        let (_, rx) = mpsc::channel();
+        let mut computer = Computer::with_bus(tx, rx, bus);
+        computer.set_cycle_stepped(cycle_stepped);
+        computer.set_pc(0x0200);
+        for _ in 0..3 {
+            computer.next_instruction();
+        }
+        assert_eq!(computer.processor.acc, 0x00);
+        assert_eq!(computer.peek(0xc000), 0x00);
+        assert_eq!(computer.bus.reads, 1);
+    }
+}
+
+/// Asserts NMI while its register holds a non zero value
+#[derive(Debug, Default)]
+struct NmiLatch {
+    value: u8,
+}
+
+impl Device for NmiLatch {
+    fn name(&self) -> &str {
+        "NMI latch"
+    }
+
+    fn read(&mut self, offset: u32) -> u8 {
+        self.peek(offset)
+    }
+
+    fn write(&mut self, _offset: u32, value: u8) {
+        self.value = value;
+    }
+
+    fn peek(&self, _offset: u32) -> u8 {
+        self.value
+    }
+
+    fn nmi(&self) -> bool {
+        self.value != 0
+    }
+}
+
+#[test]
+fn device_raises_nmi() {
+    // LDA #1, STA $D000, NOP with the NMI handler at $0300
+    let mut data = vec![0; 0x10000];
+    data[0x0200..0x0206].copy_from_slice(&[0xa9, 0x01, 0x8d, 0x00, 0xd0, 0xea]);
+    data[0xfffa] = 0x00;
+    data[0xfffb] = 0x03;
+    for &cycle_stepped in &[false, true] {
+        let mut bus = Devices::new(Ram::new(data.clone()));
+        bus.attach(0xd000, 0xd000, Box::new(NmiLatch::default())).unwrap();
+        let (tx, _) = mpsc::channel();
+        let (_, rx) = mpsc::channel();
+        let mut computer = Computer::with_bus(tx, rx, bus);
+        computer.set_cycle_stepped(cycle_stepped);
+        computer.set_pc(0x0200);
+        for _ in 0..3 {
+            computer.next_instruction();
+        }
+        assert_eq!(computer.processor.pc, 0x0300);
+        assert_eq!(computer.peek(0xd000), 0x01);
+    }
+}
+
+#[test]
+fn jump_targets_are_named() {
+    let mut symbols = Symbols::default();
+    symbols.add("target", 0x1234);
+    // JMP $1234
+    let mut computer = computer(Variant::Nmos6502, false, &[0x4c, 0x34, 0x12], &[]);
+    computer.set_symbols(symbols);
+    computer.next_instruction();
+    let info = &computer.processor.info.last().unwrap().msg;
+    assert_eq!(info, "Running instruction jmp: 0x4c jumping to: 0x1234 (target)");
+}
+
+#[test]
+fn stack_accesses_show_on_the_bus() {
+    // LDA #$42, PHA
+    let mut computer = computer(Variant::Nmos6502, false, &[0xa9, 0x42, 0x48], &[]);
+    let sp = computer.processor.sp;
+    computer.next_instruction();
+    computer.next_instruction();
+    let push = BusCycle { address: 0x0100 + sp as u32, value: 0x42, write: true };
+    assert_eq!(computer.processor.bus, Some(push));
+}
+
+#[test]
+fn arr_decimal_mode() {
+    // SED, LDA #$FF, ARR #$FF
+    let program = [0xf8, 0xa9, 0xff, 0x6b, 0xff];
+    assert_eq!(run(Variant::Nmos6502, &program, &[], 3).processor.acc, 0xd5);
+    // The 2A03 has no decimal mode
+    assert_eq!(run(Variant::Ricoh2a03, &program, &[], 3).processor.acc, 0x7f);
+}
+
+/// Feedback register driving IRQ (bit 0) and NMI (bit 1) in the interrupt tests
+const INTERRUPT_PORT: u16 = 0xbffc;
+
+/// The NMI vector goes to an RTI at $0380, the IRQ vector to an RTI at
+/// $0300 and the reset vector to $0400
+const VECTORS: [(u16, u8); 8] = [
+    (0xfffa, 0x80), (0xfffb, 0x03), (0xfffc, 0x00), (0xfffd, 0x04),
+    (0xfffe, 0x00), (0xffff, 0x03), (0x0300, 0x40), (0x0380, 0x40),
+];
+
+fn with_port(computer: &mut Computer) {
+    computer.set_interrupt_port(Some(INTERRUPT_PORT));
+}
+
+/// The status pushed by the last interrupt sequence
+fn pushed_status(computer: &Computer) -> u8 {
+    computer.peek(0x0100 + computer.processor.sp as u16 + 1)
+}
+
+#[test]
+fn irq_is_level_triggered_and_masked() {
+    // LDA #1, STA port, NOP, CLI, NOP, NOP
+    let program = [0xa9, 0x01, 0x8d, 0xfc, 0xbf, 0xea, 0x58, 0xea, 0xea];
+    let run = |count| run_with(Variant::Nmos6502, &program, &VECTORS, count, with_port);
+    // I is set after reset, and the NOP after CLI still runs
+    assert_eq!(run(5).processor.pc, 0x0208);
+    let computer = run(6);
+    assert_eq!(computer.processor.pc, 0x0300);
+    assert!(computer.processor.interrupt());
+    assert_eq!(pushed_status(&computer) & BREAK, 0);
+    assert_eq!((computer.peek(0x01fc), computer.peek(0x01fd)), (0x08, 0x02));
+    // RTI clears I right away and the line is still asserted
+    assert_eq!(run(8).processor.pc, 0x0300);
+}
+
+#[test]
+fn sei_and_plp_change_i_after_the_next_poll() {
+    // LDA #1, STA port, CLI, SEI, NOP: one IRQ gets through after SEI
+    let program = [0xa9, 0x01, 0x8d, 0xfc, 0xbf, 0x58, 0x78, 0xea];
+    assert_eq!(run_with(Variant::Nmos6502, &program, &VECTORS, 4, with_port).processor.pc, 0x0207);
+    assert_eq!(run_with(Variant::Nmos6502, &program, &VECTORS, 5, with_port).processor.pc, 0x0300);
+    // LDA #1, STA port, LDA #$20, PHA, PLP, NOP, NOP: the first NOP runs
+    let program = [0xa9, 0x01, 0x8d, 0xfc, 0xbf, 0xa9, 0x20, 0x48, 0x28, 0xea, 0xea];
+    assert_eq!(run_with(Variant::Nmos6502, &program, &VECTORS, 6, with_port).processor.pc, 0x020a);
+    assert_eq!(run_with(Variant::Nmos6502, &program, &VECTORS, 7, with_port).processor.pc, 0x0300);
+}
+
+#[test]
+fn nmi_is_edge_triggered() {
+    // LDA #2, STA port, NOP, NOP, LDA #0, STA port, LDA #2, STA port, NOP
+    let program = [
+        0xa9, 0x02, 0x8d, 0xfc, 0xbf, 0xea, 0xea, 0xa9, 0x00, 0x8d, 0xfc, 0xbf,
+        0xa9, 0x02, 0x8d, 0xfc, 0xbf, 0xea,
+    ];
+    let run = |count| run_with(Variant::Nmos6502, &program, &VECTORS, count, with_port);
+    // I is set but does not mask NMI
+    let computer = run(3);
+    assert_eq!(computer.processor.pc, 0x0380);
+    assert_eq!(pushed_status(&computer) & BREAK, 0);
+    // No second NMI while the line stays asserted
+    assert_eq!(run(6).processor.pc, 0x0207);
+    assert_eq!(run(11).processor.pc, 0x0380);
+}
+
+#[test]
+fn reset_does_not_write_the_stack() {
+    let mut bytes = VECTORS.to_vec();
+    bytes.extend_from_slice(&[(0x01fb, 0xaa), (0x01fc, 0xaa), (0x01fd, 0xaa)]);
+    let computer = run_with(Variant::Nmos6502, &[0xea], &bytes, 1, |computer| {
+        computer.processor.set_interrupt(false);
+        computer.interrupts.reset();
+    });
+    assert_eq!((computer.processor.pc, computer.processor.sp), (0x0400, 0xfa));
+    assert!(computer.processor.interrupt());
+    assert_eq!((computer.peek(0x01fb), computer.peek(0x01fc), computer.peek(0x01fd)), (0xaa, 0xaa, 0xaa));
+    assert_eq!(computer.processor.bus, Some(BusCycle { address: 0xfffd, value: 0x04, write: false }));
+}
+
+#[test]
+fn status_flags() {
+    // LDA #$80, LDA #$01: N follows bit 7 both ways
+    assert!(!run(Variant::Nmos6502, &[0xa9, 0x80, 0xa9, 0x01], &[], 2).processor.negative());
+    // LDA #$10, CMP #$10: equal sets C and Z
+    let computer = run(Variant::Nmos6502, &[0xa9, 0x10, 0xc9, 0x10], &[], 2);
+    assert_eq!(computer.processor.flags & (CARRY | ZERO), CARRY | ZERO);
+    // CLC, LDA #$7F, ADC #$01: signed overflow without carry
+    let computer = run(Variant::Nmos6502, &[0x18, 0xa9, 0x7f, 0x69, 0x01], &[], 3);
+    assert!(computer.processor.overflow() && computer.processor.negative() && !computer.processor.carry());
+    // PHP pushes B and bit 5, I is set after reset
+    assert_eq!(run(Variant::Nmos6502, &[0x08], &[], 1).peek(0x01fd), BREAK | UNUSED | INTERRUPT);
+    // BRK pushes B too, unlike IRQ and NMI
+    let computer = run(Variant::Nmos6502, &[0x00, 0x00], &VECTORS, 1);
+    assert_eq!(computer.processor.pc, 0x0300);
+    assert_eq!(pushed_status(&computer) & (BREAK | UNUSED), BREAK | UNUSED);
+}
+
+/// The cycles `count` instructions of `program` take
+fn cycles(program: &[u8], count: usize) -> u64 {
+    let start = computer(Variant::Nmos6502, false, program, &[]).processor.clock;
+    run(Variant::Nmos6502, program, &[], count).processor.clock - start
+}
+
+#[test]
+fn page_crossing_penalties() {
+    // LDX #$00, LDA $02F0,X
+    assert_eq!(cycles(&[0xa2, 0x00, 0xbd, 0xf0, 0x02], 2), 2 + 4);
+    // LDX #$20, LDA $02F0,X reads $0310
+    assert_eq!(cycles(&[0xa2, 0x20, 0xbd, 0xf0, 0x02], 2), 2 + 5);
+    // LDA #1, BEQ not taken
+    assert_eq!(cycles(&[0xa9, 0x01, 0xf0, 0x02], 2), 2 + 2);
+    // LDA #1, BNE to $0206 in the same page
+    assert_eq!(cycles(&[0xa9, 0x01, 0xd0, 0x02], 2), 2 + 3);
+    // LDA #1, BNE to $01F4 in the page before
+    assert_eq!(cycles(&[0xa9, 0x01, 0xd0, 0xf0], 2), 2 + 4);
+}
+
+/// Run `count` instructions of `program` on a 65C816 after `setup`. The
+/// 65C816 has no cycle-stepped core.
+fn native(program: &[u8], bytes: &[(u16, u8)], count: usize, setup: impl Fn(&mut Computer)) -> Computer {
+    let mut computer = computer(Variant::W65c816, false, program, bytes);
+    setup(&mut computer);
+    for _ in 0..count {
+        computer.next_instruction();
+    }
+    computer
+}
+
+/// The whole 16-bit accumulator
+fn c(computer: &Computer) -> u16 {
+    (computer.processor.acc_high as u16) << 8 | computer.processor.acc as u16
+}
+
+#[test]
+fn native_mode_register_widths() {
+    // CLC, XCE, REP #$30, LDA #$1234, LDX #$5678, SEP #$20, LDA #$FF, SEP #$10, SEC, XCE
+    let program = [
+        0x18, 0xfb, 0xc2, 0x30, 0xa9, 0x34, 0x12, 0xa2, 0x78, 0x56, 0xe2, 0x20, 0xa9, 0xff,
+        0xe2, 0x10, 0x38, 0xfb,
+    ];
+    let run = |count| native(&program, &[], count, |_| {});
+    // Native mode starts with 8-bit registers, C gets the old E
+    let computer = run(2);
+    assert!(!computer.processor.emulation && computer.processor.carry());
+    assert_eq!(computer.processor.flags & (MEMORY_8 | INDEX_8), MEMORY_8 | INDEX_8);
+    // 8-bit loads leave B alone
+    let computer = run(7);
+    assert_eq!((c(&computer), computer.processor.rx, computer.processor.rx_high), (0x12ff, 0x78, 0x56));
+    // 8-bit index registers lose their high byte
+    assert_eq!(run(8).processor.rx_high, 0);
+    let computer = run(10);
+    assert!(computer.processor.emulation && !computer.processor.carry());
+    assert_eq!(computer.processor.sp_high, 1);
+}
+
+#[test]
+fn native_16_bit_arithmetic() {
+    // CLC, XCE, REP #$30, CLC, LDA #$7FFF, ADC #$0001, CMP #$8000, SEC, SBC #$0001
+    let program = [
+        0x18, 0xfb, 0xc2, 0x30, 0x18, 0xa9, 0xff, 0x7f, 0x69, 0x01, 0x00, 0xc9, 0x00, 0x80,
+        0x38, 0xe9, 0x01, 0x00,
+    ];
+    let run = |count| native(&program, &[], count, |_| {});
+    let computer = run(6);
+    assert_eq!((c(&computer), computer.processor.flags & (NEGATIVE | OVERFLOW | CARRY)), (0x8000, NEGATIVE | OVERFLOW));
+    assert_eq!(run(7).processor.flags & (ZERO | CARRY), ZERO | CARRY);
+    let computer = run(9);
+    assert_eq!((c(&computer), computer.processor.flags & (OVERFLOW | CARRY)), (0x7fff, OVERFLOW | CARRY));
+
+    // CLC, XCE, REP #$30, SED, CLC, LDA #$1999, ADC #$0001, ADC #$8000, SEC, SBC #$0001
+    let program = [
+        0x18, 0xfb, 0xc2, 0x30, 0xf8, 0x18, 0xa9, 0x99, 0x19, 0x69, 0x01, 0x00, 0x69, 0x00, 0x80,
+        0x38, 0xe9, 0x01, 0x00,
+    ];
+    let run = |count| native(&program, &[], count, |_| {});
+    let computer = run(7);
+    assert_eq!((c(&computer), computer.processor.carry()), (0x2000, false));
+    let computer = run(8);
+    assert_eq!((c(&computer), computer.processor.flags & (ZERO | CARRY)), (0x0000, ZERO | CARRY));
+    let computer = run(10);
+    assert_eq!((c(&computer), computer.processor.flags & (NEGATIVE | CARRY | DECIMAL)), (0x9999, NEGATIVE | DECIMAL));
+}
+
+#[test]
+fn native_addressing_modes() {
+    let program = [
+        0x18, 0xfb, // CLC, XCE
+        0xa9, 0x01, 0x48, 0xab, // LDA #$01, PHA, PLB
+        0xc2, 0x20, 0xa9, 0x00, 0x12, 0x5b, // REP #$20, LDA #$1200, TCD
+        0xa5, 0x34, // LDA $34
+        0xad, 0x00, 0x30, // LDA $3000
+        0xaf, 0x00, 0x40, 0x02, // LDA $024000
+        0xf4, 0x66, 0x55, 0xa3, 0x01, // PEA $5566, LDA 1,S
+        0x5c, 0x00, 0x80, 0x03, // JML $038000
+    ];
+    let bytes = [(0x1234, 0xcd), (0x1235, 0xab), (0x3000, 0xee), (0x3001, 0xee)];
+    let run = |count| native(&program, &bytes, count, |computer| {
+        for (address, value) in [(0x01_3000, 0x11), (0x01_3001, 0x22), (0x02_4000, 0x33), (0x02_4001, 0x44)] {
+            computer.bus.write(address, value);
+        }
+        // LDA #$8899
+        for (address, value) in [(0x03_8000, 0xa9), (0x03_8001, 0x99), (0x03_8002, 0x88)] {
+            computer.bus.write(address, value);
+        }
+    });
+    // The direct page is in bank 0 whatever DBR is
+    let computer = run(9);
+    assert_eq!((computer.processor.dbr, computer.processor.dp, c(&computer)), (0x01, 0x1200, 0xabcd));
+    assert_eq!(c(&run(10)), 0x2211);
+    assert_eq!(c(&run(11)), 0x4433);
+    assert_eq!(c(&run(13)), 0x5566);
+    let computer = run(15);
+    assert_eq!((computer.processor.pbr, computer.processor.pc, c(&computer)), (0x03, 0x8003, 0x8899));
+}
+
+#[test]
+fn block_moves() {
+    let bytes = [(0x1000, 1), (0x1001, 2), (0x1002, 3)];
+    // CLC, XCE, REP #$30, LDA #$0002, LDX #$1000, LDY #$2000, MVN $01,$00
+    let program = [0x18, 0xfb, 0xc2, 0x30, 0xa9, 0x02, 0x00, 0xa2, 0x00, 0x10, 0xa0, 0x00, 0x20, 0x54, 0x01, 0x00];
+    // MVN moves a byte each time it runs
+    assert_eq!(native(&program, &bytes, 7, |_| {}).processor.pc, 0x020d);
+    let computer = native(&program, &bytes, 9, |_| {});
+    assert_eq!((computer.processor.pc, c(&computer), computer.processor.dbr), (0x0210, 0xffff, 0x01));
+    assert_eq!((computer.processor.rx, computer.processor.rx_high, computer.processor.ry_high), (0x03, 0x10, 0x20));
+    assert_eq!([0x01_2000, 0x01_2001, 0x01_2002].map(|address| computer.bus.peek(address)), [1, 2, 3]);
+
+    // The same with LDX #$1002, LDY #$2002, MVP $01,$00 going down
+    let program = [0x18, 0xfb, 0xc2, 0x30, 0xa9, 0x02, 0x00, 0xa2, 0x02, 0x10, 0xa0, 0x02, 0x20, 0x44, 0x01, 0x00];
+    let computer = native(&program, &bytes, 9, |_| {});
+    assert_eq!((computer.processor.rx, computer.processor.rx_high, computer.processor.ry, computer.processor.ry_high), (0xff, 0x0f, 0xff, 0x1f));
+    assert_eq!([0x01_2000, 0x01_2001, 0x01_2002].map(|address| computer.bus.peek(address)), [1, 2, 3]);
+}
+
+#[test]
+fn long_subroutines() {
+    // CLC, XCE, JSL $038000, NOP with an RTL at $038000
+    let program = [0x18, 0xfb, 0x22, 0x00, 0x80, 0x03, 0xea];
+    let run = |count| native(&program, &[], count, |computer| computer.bus.write(0x03_8000, 0x6b));
+    // The program bank, then the address of the last byte of the JSL
+    let computer = run(3);
+    assert_eq!((computer.processor.pbr, computer.processor.pc, computer.processor.sp), (0x03, 0x8000, 0xfa));
+    assert_eq!((computer.peek(0x01fd), computer.peek(0x01fc), computer.peek(0x01fb)), (0x00, 0x02, 0x05));
+    let computer = run(4);
+    assert_eq!((computer.processor.pbr, computer.processor.pc, computer.processor.sp), (0x00, 0x0206, 0xfd));
+}
+
+#[test]
+fn native_interrupts() {
+    // The native BRK and NMI vectors go to an RTI at $0300
+    let bytes = [(0xffe6, 0x00), (0xffe7, 0x03), (0xffea, 0x00), (0xffeb, 0x03), (0x0300, 0x40)];
+    // CLC, XCE, JML $038000
+    let program = [0x18, 0xfb, 0x5c, 0x00, 0x80, 0x03];
+    // BRK at $038000
+    let computer = native(&program, &bytes, 4, |computer| computer.bus.write(0x03_8000, 0x00));
+    assert_eq!((computer.processor.pbr, computer.processor.pc, computer.processor.sp), (0x00, 0x0300, 0xf9));
+    assert_eq!((computer.peek(0x01fd), computer.peek(0x01fc), computer.peek(0x01fb)), (0x03, 0x80, 0x02));
+    assert!(computer.processor.interrupt() && !computer.processor.decimal());
+    let computer = native(&program, &bytes, 5, |computer| computer.bus.write(0x03_8000, 0x00));
+    assert_eq!((computer.processor.pbr, computer.processor.pc, computer.processor.sp), (0x03, 0x8002, 0xfd));
+
+    // LDA #2, STA $BFFC at $038000 raises NMI
+    let run = |count| native(&program, &bytes, count, |computer| {
+        with_port(computer);
+        for (address, value) in [(0x03_8000, 0xa9), (0x03_8001, 0x02), (0x03_8002, 0x8d), (0x03_8003, 0xfc), (0x03_8004, 0xbf)] {
+            computer.bus.write(address, value);
+        }
+    });
+    let computer = run(6);
+    assert_eq!((computer.processor.pbr, computer.processor.pc), (0x00, 0x0300));
+    assert_eq!((computer.peek(0x01fd), computer.peek(0x01fc), computer.peek(0x01fb)), (0x03, 0x80, 0x05));
+    let computer = run(7);
+    assert_eq!((computer.processor.pbr, computer.processor.pc), (0x03, 0x8005));
+}
+
+/// Klaus Dormann's functional test, which traps at $3469 once every test
+/// passed. It runs about 30 million instructions, too slow without
+/// optimizations: run with `cargo test --release -- --ignored`
+fn functional_test(cycle_stepped: bool) -> u16 {
+    let (tx, _) = mpsc::channel();
+    let (_, rx) = mpsc::channel();
+    let mut computer = Computer::new(tx, rx, include_bytes!("../../6502_functional_test.bin").to_vec());
+    computer.set_cycle_stepped(cycle_stepped);
+    computer.set_pc(0x0400);
+    computer.run_until_trap()
+}
+
+#[test]
+#[ignore]
+fn functional_test_instruction_stepped() {
+    assert_eq!(functional_test(false), 0x3469);
+}
+
+#[test]
+#[ignore]
+fn functional_test_cycle_stepped() {
+    assert_eq!(functional_test(true), 0x3469);
+}
@@ -0,0 +1,127 @@
+use std::sync::mpsc;
+
+use crate::computer::Computer;
+use crate::variant::Variant;
+
+// Bruce Clark's decimal mode verification program, from the
+// "Decimal Mode" tutorial on 6502.org (appendix B), assembled at $0400.
+// It adds and subtracts every pair of operands with both carry values and
+// compares the result and the N, V, Z and C flags with a prediction computed
+// with binary arithmetic. Unlike the functional test this also covers invalid
+// BCD operands and the flags the functional test ignores.
+//
+// $0400  jsr TEST
+// $0403  jmp $0403
+//
+// The zero page variables are at $10-$20, ERROR is 0 once the test passed.
+// The 65C02 sets N and Z from the decimal result, its run calls the
+// A65C02 and S65C02 predictions instead of A6502 and S6502.
+
+/// Where the program is loaded and started
+pub const START: u16 = 0x0400;
+
+/// 0 if the test passed, 1 otherwise
+const ERROR: u16 = 0x14;
+const N1: u16 = 0x17;
+const N2: u16 = 0x1a;
+/// Actual result and flags in decimal mode
+const DA: u16 = 0x12;
+const DNVZC: u16 = 0x13;
+/// Predicted result
+const AR: u16 = 0x10;
+/// Targets of the JSRs to the flag predictions, and the 65C02 routines
+const PADD_TARGET: usize = 0x0430;
+const PSUB_TARGET: usize = 0x043b;
+const A65C02: u16 = 0x0527;
+const S65C02: u16 = 0x0530;
+
+const PROGRAM: [u8; 322] = [
+    0x20, 0x06, 0x04, 0x4c, 0x03, 0x04, 0xa0, 0x01, 0x84, 0x14, 0xa9, 0x00, 0x85, 0x17, 0x85, 0x1a,
+    0xa5, 0x1a, 0x29, 0x0f, 0x85, 0x1b, 0xa5, 0x1a, 0x29, 0xf0, 0x85, 0x1f, 0x09, 0x0f, 0x85, 0x20,
+    0xa5, 0x17, 0x29, 0x0f, 0x85, 0x19, 0xa5, 0x17, 0x29, 0xf0, 0x85, 0x18, 0x20, 0x52, 0x04, 0x20,
+    0x10, 0x05, 0x20, 0xeb, 0x04, 0xd0, 0x1a, 0x20, 0x96, 0x04, 0x20, 0x19, 0x05, 0x20, 0xeb, 0x04,
+    0xd0, 0x0f, 0xe6, 0x17, 0xd0, 0xda, 0xe6, 0x1a, 0xd0, 0xc6, 0x88, 0x10, 0xc3, 0xa9, 0x00, 0x85,
+    0x14, 0x60, 0xf8, 0xc0, 0x01, 0xa5, 0x17, 0x65, 0x1a, 0x85, 0x12, 0x08, 0x68, 0x85, 0x13, 0xd8,
+    0xc0, 0x01, 0xa5, 0x17, 0x65, 0x1a, 0x85, 0x15, 0x08, 0x68, 0x85, 0x16, 0xc0, 0x01, 0xa5, 0x19,
+    0x65, 0x1b, 0xc9, 0x0a, 0xa2, 0x00, 0x90, 0x06, 0xe8, 0x69, 0x05, 0x29, 0x0f, 0x38, 0x05, 0x18,
+    0x75, 0x1f, 0x08, 0xb0, 0x04, 0xc9, 0xa0, 0x90, 0x03, 0x69, 0x5f, 0x38, 0x85, 0x10, 0x08, 0x68,
+    0x85, 0x11, 0x68, 0x85, 0x1d, 0x60, 0xf8, 0xc0, 0x01, 0xa5, 0x17, 0xe5, 0x1a, 0x85, 0x12, 0x08,
+    0x68, 0x85, 0x13, 0xd8, 0xc0, 0x01, 0xa5, 0x17, 0xe5, 0x1a, 0x85, 0x15, 0x08, 0x68, 0x85, 0x16,
+    0x60, 0xc0, 0x01, 0xa5, 0x19, 0xe5, 0x1b, 0xa2, 0x00, 0xb0, 0x06, 0xe8, 0xe9, 0x05, 0x29, 0x0f,
+    0x18, 0x05, 0x18, 0xf5, 0x1f, 0xb0, 0x02, 0xe9, 0x5f, 0x85, 0x10, 0x60, 0xc0, 0x01, 0xa5, 0x19,
+    0xe5, 0x1b, 0xa2, 0x00, 0xb0, 0x04, 0xe8, 0x29, 0x0f, 0x18, 0x05, 0x18, 0xf5, 0x1f, 0xb0, 0x02,
+    0xe9, 0x5f, 0xe0, 0x00, 0xf0, 0x02, 0xe9, 0x06, 0x85, 0x10, 0x60, 0xa5, 0x12, 0xc5, 0x10, 0xd0,
+    0x1e, 0xa5, 0x13, 0x45, 0x1c, 0x29, 0x80, 0xd0, 0x16, 0xa5, 0x13, 0x45, 0x1d, 0x29, 0x40, 0xd0,
+    0x0e, 0xa5, 0x13, 0x45, 0x1e, 0x29, 0x02, 0xd0, 0x06, 0xa5, 0x13, 0x45, 0x11, 0x29, 0x01, 0x60,
+    0xa5, 0x1d, 0x85, 0x1c, 0xa5, 0x16, 0x85, 0x1e, 0x60, 0x20, 0xb1, 0x04, 0xa5, 0x16, 0x85, 0x1c,
+    0x85, 0x1d, 0x85, 0x1e, 0x85, 0x11, 0x60, 0xa5, 0x10, 0x08, 0x68, 0x85, 0x1c, 0x85, 0x1e, 0x60,
+    0x20, 0xcc, 0x04, 0xa5, 0x10, 0x08, 0x68, 0x85, 0x1c, 0x85, 0x1e, 0xa5, 0x16, 0x85, 0x1d, 0x85,
+    0x11, 0x60,
+];
+
+/// A full 64KiB memory image with the test program for `variant` loaded
+/// and the reset vector pointing at it
+pub fn image(variant: Variant) -> Vec<u8> {
+    let mut data = vec![0; 0x10000];
+    let start = START as usize;
+    data[start..start + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    if variant.cpu().cmos() {
+        data[PADD_TARGET..PADD_TARGET + 2].copy_from_slice(&A65C02.to_le_bytes());
+        data[PSUB_TARGET..PSUB_TARGET + 2].copy_from_slice(&S65C02.to_le_bytes());
+    }
+    data[0xfffc] = START as u8;
+    data[0xfffd] = (START >> 8) as u8;
+    data
+}
+
+/// Run the test program on `variant` without the UI
+pub fn run(variant: Variant) -> Result<(), String> {
+    let cpu = variant.cpu();
+    if !cpu.decimal() {
+        return Err(format!("the {} has no decimal mode", cpu.name()));
+    }
+    let (tx, _rx) = mpsc::channel();
+    let (_computer_tx, computer_rx) = mpsc::channel();
+    let mut computer = Computer::new(tx, computer_rx, image(variant));
+    computer.set_variant(variant);
+    computer.run_until_trap();
+
+    if computer.peek(ERROR) == 0 {
+        return Ok(());
+    }
+
+    Err(format!(
+        "N1: {:#04x} N2: {:#04x} carry: {} result: {:#04x} expected: {:#04x} flags: {:#010b}",
+        computer.peek(N1),
+        computer.peek(N2),
+        computer.processor().ry,
+        computer.peek(DA),
+        computer.peek(AR),
+        computer.peek(DNVZC),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every pair of operands is tried, too slow without optimizations:
+    // run with `cargo test --release -- --ignored`
+
+    #[test]
+    #[ignore]
+    fn nmos_decimal_mode() {
+        assert_eq!(run(Variant::Nmos6502), Ok(()));
+    }
+
+    #[test]
+    #[ignore]
+    fn cmos_decimal_mode() {
+        assert_eq!(run(Variant::Cmos65c02), Ok(()));
+    }
+
+    #[test]
+    fn no_decimal_mode_on_the_2a03() {
+        assert!(run(Variant::Ricoh2a03).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Memory mapped devices: peripherals attached at a range of addresses that
+//! answer the processor's accesses there, run alongside it and may drive
+//! its interrupt lines.
+
+use std::fmt::Debug;
+
+use crate::bus::Bus;
+
+/// A peripheral. Addresses given to it are offsets from the start of the
+/// range it is attached at, so the same device can sit anywhere.
+pub trait Device: Debug + Send {
+    fn name(&self) -> &str;
+
+    /// A read of one of the registers by the processor, which may have side
+    /// effects like acknowledging an interrupt
+    fn read(&mut self, offset: u32) -> u8;
+
+    /// A write to one of the registers by the processor
+    fn write(&mut self, offset: u32, value: u8);
+
+    /// The value a read would return, without its side effects
+    fn peek(&self, offset: u32) -> u8;
+
+    /// Let `cycles` clock cycles go by
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// The reset line of the board was asserted
+    fn reset(&mut self) {}
+
+    /// Whether the device asserts IRQ. IRQ is shared, the processor sees it
+    /// asserted as long as one of the devices asserts it.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether the device asserts NMI. An NMI happens when the line goes
+    /// from released to asserted.
+    fn nmi(&self) -> bool {
+        false
+    }
+}
+
+/// A device and the addresses it answers, both inclusive
+#[derive(Debug)]
+struct Attached {
+    start: u32,
+    end: u32,
+    device: Box<dyn Device>,
+}
+
+/// A bus with devices attached over it. Accesses in the range of a device
+/// go to it, the others go to the bus below.
+#[derive(Debug)]
+pub struct Devices<B: Bus> {
+    inner: B,
+    devices: Vec<Attached>,
+}
+
+impl<B: Bus> Devices<B> {
+    pub fn new(inner: B) -> Devices<B> {
+        Devices { inner, devices: vec![] }
+    }
+
+    /// Attach `device` at `start`-`end`, the range must not overlap the
+    /// ones of the other devices
+    pub fn attach(&mut self, start: u32, end: u32, device: Box<dyn Device>) -> Result<(), String> {
+        if end < start {
+            return Err(format!("The range of {} ends before it starts", device.name()));
+        }
+        if let Some(other) = self.devices.iter().find(|d| d.start <= end && start <= d.end) {
+            return Err(format!("{} overlaps {}", device.name(), other.device.name()));
+        }
+        self.devices.push(Attached { start, end, device });
+        Ok(())
+    }
+
+    /// A line per device with its range, for the UI
+    pub fn describe(&self) -> Vec<String> {
+        self.devices.iter()
+            .map(|d| format!("${:04X}-${:04X} {}", d.start, d.end, d.device.name()))
+            .collect()
+    }
+
+    fn find(&self, address: u32) -> Option<usize> {
+        self.devices.iter().position(|d| d.start <= address && address <= d.end)
+    }
+}
+
+impl<B: Bus> Bus for Devices<B> {
+    fn read(&mut self, address: u32) -> u8 {
+        match self.find(address) {
+            Some(index) => {
+                let attached = &mut self.devices[index];
+                attached.device.read(address - attached.start)
+            },
+            None => self.inner.read(address),
+        }
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        match self.find(address) {
+            Some(index) => {
+                let attached = &mut self.devices[index];
+                attached.device.write(address - attached.start, value);
+            },
+            None => self.inner.write(address, value),
+        }
+    }
+
+    fn peek(&self, address: u32) -> u8 {
+        match self.find(address) {
+            Some(index) => {
+                let attached = &self.devices[index];
+                attached.device.peek(address - attached.start)
+            },
+            None => self.inner.peek(address),
+        }
+    }
+
+    fn take_fault(&mut self) -> Option<String> {
+        self.inner.take_fault()
+    }
+
+    fn banks(&self) -> Vec<String> {
+        self.inner.banks()
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+        for attached in self.devices.iter_mut() {
+            attached.device.tick(cycles);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        for attached in self.devices.iter_mut() {
+            attached.device.reset();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.inner.irq() || self.devices.iter().any(|d| d.device.irq())
+    }
+
+    fn nmi(&self) -> bool {
+        self.inner.nmi() || self.devices.iter().any(|d| d.device.nmi())
+    }
+}
+
+/// The device called `name`, as given to `--device`, with the number of
+/// addresses it takes
+pub fn create(name: &str) -> Result<(Box<dyn Device>, u32), String> {
+    match name.to_lowercase().as_str() {
+        "timer" => Ok((Box::new(Timer::default()), 2)),
+        _ => Err(format!("Unknown device {}", name)),
+    }
+}
+
+/// A one-shot interval timer. Writing the low then the high byte of a count
+/// of clock cycles starts it, and it asserts IRQ when the count runs out.
+/// Reading the low byte acknowledges the interrupt.
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: u64,
+    /// Low byte of the next count, until the high byte is written
+    latch: u8,
+    irq: bool,
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "Timer"
+    }
+
+    fn read(&mut self, offset: u32) -> u8 {
+        if offset == 0 {
+            self.irq = false;
+        }
+        self.peek(offset)
+    }
+
+    fn write(&mut self, offset: u32, value: u8) {
+        if offset == 0 {
+            self.latch = value;
+        } else {
+            self.count = (value as u64) << 8 | self.latch as u64;
+            self.irq = false;
+        }
+    }
+
+    fn peek(&self, offset: u32) -> u8 {
+        (self.count >> (8 * offset)) as u8
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if self.count > 0 && self.count <= cycles {
+            self.irq = true;
+        }
+        self.count = self.count.saturating_sub(cycles);
+    }
+
+    fn reset(&mut self) {
+        *self = Timer::default();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Ram;
+
+    #[test]
+    fn accesses_go_to_the_device() {
+        let mut bus = Devices::new(Ram::new(vec![]));
+        let (timer, size) = create("timer").unwrap();
+        bus.attach(0xd000, 0xd000 + size - 1, timer).unwrap();
+        bus.write(0xd000, 0x34);
+        bus.write(0xd001, 0x12);
+        bus.write(0xd002, 20);
+        assert_eq!((bus.peek(0xd000), bus.peek(0xd001), bus.peek(0xd002)), (0x34, 0x12, 20));
+        assert!(bus.attach(0xd001, 0xd002, Box::new(Timer::default())).is_err());
+        assert_eq!(bus.describe(), vec!["$D000-$D001 Timer"]);
+        assert!(create("uart").is_err());
+    }
+
+    #[test]
+    fn devices_drive_irq() {
+        let mut bus = Devices::new(Ram::new(vec![]));
+        bus.attach(0xd000, 0xd001, Box::new(Timer::default())).unwrap();
+        bus.write(0xd000, 10);
+        bus.write(0xd001, 0);
+        bus.tick(6);
+        assert!(!bus.irq());
+        bus.tick(6);
+        assert!(bus.irq());
+        bus.read(0xd000);
+        assert!(!bus.irq());
+        bus.write(0xd001, 0);
+        bus.reset();
+        assert_eq!(bus.peek(0xd000), 0);
+    }
+}
@@ -0,0 +1,301 @@
+//! Program images in the formats assemblers and linkers write: raw
+//! binaries, Commodore .prg files, Intel HEX and Motorola S-records. The
+//! text formats give the address of each record and may give the address
+//! to start at.
+
+use std::fs;
+use std::path::Path;
+
+use crate::computer::place_image;
+
+/// Bytes to place at an address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// A parsed image file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    /// Where the program starts, from the start record
+    pub start: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Binary,
+    /// A binary after its load address, 2 bytes little endian
+    Prg,
+    IntelHex,
+    SRecord,
+}
+
+impl Format {
+    /// The format of a file, from its extension
+    pub fn from_filename(filename: &str) -> Format {
+        let extension = Path::new(filename).extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("prg") => Format::Prg,
+            Some("hex") | Some("ihx") => Format::IntelHex,
+            Some("s19") | Some("s28") | Some("s37") | Some("srec") => Format::SRecord,
+            _ => Format::Binary,
+        }
+    }
+}
+
+impl Image {
+    /// Parse the contents of a file in `format`. A raw binary is a single
+    /// segment at `address`, or 0. A .prg file goes to the address in its
+    /// header unless `address` is given.
+    pub fn parse(format: Format, contents: &[u8], address: Option<u16>) -> Result<Image, String> {
+        match format {
+            Format::Binary => Ok(Image::binary(contents, address.unwrap_or(0))),
+            Format::Prg => {
+                if contents.len() < 2 {
+                    return Err("A .prg file starts with its 2 byte load address".to_string());
+                }
+                let header = contents[0] as u16 | (contents[1] as u16) << 8;
+                Ok(Image::binary(&contents[2..], address.unwrap_or(header)))
+            },
+            _ if address.is_some() => Err("The file gives the addresses of its records, it cannot be placed elsewhere".to_string()),
+            Format::IntelHex => parse_intel_hex(&text(contents)?),
+            Format::SRecord => parse_srecord(&text(contents)?),
+        }
+    }
+
+    /// Read and parse the file `filename`, its format comes from its extension
+    pub fn load(filename: &str, address: Option<u16>) -> Result<Image, String> {
+        let contents = fs::read(filename).map_err(|e| format!("Could not read {}: {}", filename, e))?;
+        Image::parse(Format::from_filename(filename), &contents, address).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    fn binary(contents: &[u8], address: u16) -> Image {
+        Image {
+            segments: vec![Segment { address: address as u32, data: contents.to_vec() }],
+            start: None,
+        }
+    }
+
+    /// Copy the segments into the 64KiB memory `data`
+    pub fn place(&self, data: &mut [u8]) -> Result<(), String> {
+        for segment in &self.segments {
+            if segment.address > 0xffff {
+                return Err(format!("A record at {:#x} is past the 64KiB address space", segment.address));
+            }
+            place_image(data, &segment.data, segment.address as u16)?;
+        }
+        Ok(())
+    }
+
+    /// The start address, if the image has one
+    pub fn start_pc(&self) -> Result<Option<u16>, String> {
+        match self.start {
+            Some(start) if start > 0xffff => Err(format!("Start address {:#x} is past the 64KiB address space", start)),
+            start => Ok(start.map(|start| start as u16)),
+        }
+    }
+}
+
+/// Check that none of the named `images` overlap, the first overlap found
+/// is the error
+pub fn check_overlaps(images: &[(String, Image)]) -> Result<(), String> {
+    for (index, (name, image)) in images.iter().enumerate() {
+        for (other_name, other) in &images[index + 1..] {
+            for a in image.segments.iter().filter(|s| !s.data.is_empty()) {
+                for b in other.segments.iter().filter(|s| !s.data.is_empty()) {
+                    let start = a.address.max(b.address);
+                    let end = a.end().min(b.end());
+                    if start <= end {
+                        return Err(format!("{} and {} overlap at ${:04X}-${:04X}", name, other_name, start, end));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Segment {
+    /// Last address of the segment
+    fn end(&self) -> u32 {
+        self.address + self.data.len() as u32 - 1
+    }
+}
+
+fn text(contents: &[u8]) -> Result<String, String> {
+    String::from_utf8(contents.to_vec()).map_err(|_| "The image is not a text file".to_string())
+}
+
+/// The bytes written in hexadecimal in `digits`
+fn hex_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return Err(format!("Invalid hexadecimal {}", digits));
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("Invalid hexadecimal {}", digits)))
+        .collect()
+}
+
+/// Big endian value of `bytes`
+fn number(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u32)
+}
+
+/// Intel HEX: `:LLAAAATT` with LL data bytes, a 16-bit address and a record
+/// type, then the data and a checksum making the sum of all the bytes 0.
+/// Extended segment (02) and linear (04) address records give the upper
+/// bits of the addresses, start segment (03) and linear (05) address
+/// records give the start address.
+pub fn parse_intel_hex(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+    let mut base = 0;
+    for (line_number, line) in text.lines().enumerate().map(|(n, line)| (n + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let error = |e: String| format!("Line {}: {}", line_number, e);
+        let digits = line.strip_prefix(':').ok_or_else(|| error("Records start with ':'".to_string()))?;
+        let bytes = hex_bytes(digits).map_err(error)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("The length of the record is wrong".to_string()));
+        }
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(error("Bad checksum".to_string()));
+        }
+        let address = number(&bytes[1..3]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => image.segments.push(Segment { address: base + address, data: data.to_vec() }),
+            0x01 => break,
+            0x02 if data.len() == 2 => base = number(data) << 4,
+            0x03 if data.len() == 4 => image.start = Some((number(&data[..2]) << 4) + number(&data[2..])),
+            0x04 if data.len() == 2 => base = number(data) << 16,
+            0x05 if data.len() == 4 => image.start = Some(number(data)),
+            record_type => return Err(error(format!("Invalid record of type {:02X}", record_type))),
+        }
+    }
+    Ok(image)
+}
+
+/// Motorola S-records: `S` and the record type, the number of bytes that
+/// follow, then the address, the data and the ones' complement of their sum.
+/// S1, S2 and S3 hold data at 16, 24 and 32-bit addresses, S9, S8 and S7
+/// give the start address. The header and counts are skipped.
+pub fn parse_srecord(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+    for (line_number, line) in text.lines().enumerate().map(|(n, line)| (n + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let error = |e: String| format!("Line {}: {}", line_number, e);
+        if line.len() < 2 || !line.starts_with('S') || !line.is_char_boundary(2) {
+            return Err(error("Records start with S and the record type".to_string()));
+        }
+        let bytes = hex_bytes(&line[2..]).map_err(error)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("The length of the record is wrong".to_string()));
+        }
+        let (checksum, bytes) = bytes.split_last().unwrap();
+        if !bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != *checksum {
+            return Err(error("Bad checksum".to_string()));
+        }
+        let address_size = match &line[1..2] {
+            "0" | "5" | "6" => continue,
+            "1" | "9" => 2,
+            "2" | "8" => 3,
+            "3" | "7" => 4,
+            record_type => return Err(error(format!("Invalid record type S{}", record_type))),
+        };
+        if bytes.len() < 1 + address_size {
+            return Err(error("The record is too short for its address".to_string()));
+        }
+        let address = number(&bytes[1..1 + address_size]);
+        match &line[1..2] {
+            "1" | "2" | "3" => image.segments.push(Segment { address, data: bytes[1 + address_size..].to_vec() }),
+            _ => image.start = Some(address),
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::MEMORY_SIZE;
+
+    #[test]
+    fn formats_from_extensions() {
+        assert_eq!(Format::from_filename("6502_functional_test.hex"), Format::IntelHex);
+        assert_eq!(Format::from_filename("rom.S19"), Format::SRecord);
+        assert_eq!(Format::from_filename("6502_functional_test.bin"), Format::Binary);
+        assert_eq!(Format::from_filename("image"), Format::Binary);
+    }
+
+    #[test]
+    fn prg_header() {
+        let image = Image::parse(Format::Prg, &[0x01, 0x08, 0x0b, 0x08], None).unwrap();
+        assert_eq!(image.segments, vec![Segment { address: 0x0801, data: vec![0x0b, 0x08] }]);
+        let image = Image::parse(Format::Prg, &[0x01, 0x08, 0x0b, 0x08], Some(0xc000)).unwrap();
+        assert_eq!(image.segments[0].address, 0xc000);
+        assert!(Image::parse(Format::Prg, &[0x01], None).is_err());
+        assert!(Image::parse(Format::IntelHex, b":00000001FF", Some(0xc000)).is_err());
+    }
+
+    #[test]
+    fn overlaps() {
+        let rom = ("rom.bin".to_string(), Image::binary(&[0; 0x2000], 0xe000));
+        let program = ("prog.bin".to_string(), Image::binary(&[0; 0x100], 0x0800));
+        let patch = ("patch.bin".to_string(), Image::binary(&[0; 0x8], 0xfff8));
+        assert_eq!(check_overlaps(&[rom.clone(), program.clone()]), Ok(()));
+        assert_eq!(check_overlaps(&[rom, program, patch]), Err("rom.bin and patch.bin overlap at $FFF8-$FFFF".to_string()));
+    }
+
+    #[test]
+    fn intel_hex() {
+        let image = parse_intel_hex("
+            :0300000002003CBF
+            :020000040001F9
+            :02123400A942CD
+            :0400000500000400F3
+            :00000001FF
+        ").unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { address: 0x0000, data: vec![0x02, 0x00, 0x3c] },
+            Segment { address: 0x11234, data: vec![0xa9, 0x42] },
+        ]);
+        assert_eq!(image.start, Some(0x0400));
+        assert!(parse_intel_hex(":0300000002003CBE").is_err());
+        assert!(parse_intel_hex(":03000000020000").is_err());
+    }
+
+    #[test]
+    fn srecord() {
+        let image = parse_srecord("
+            S00600004844521B
+            S1060400A942EA20
+            S2070104008D000264
+            S9030400F8
+        ").unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { address: 0x0400, data: vec![0xa9, 0x42, 0xea] },
+            Segment { address: 0x10400, data: vec![0x8d, 0x00, 0x02] },
+        ]);
+        assert_eq!(image.start_pc(), Ok(Some(0x0400)));
+        assert!(parse_srecord("S1060400A942EA21").is_err());
+        assert!(parse_srecord("X1060400A942EA20").is_err());
+    }
+
+    #[test]
+    fn records_are_placed_at_their_address() {
+        let image = parse_srecord("S1060400A942EA20\nS9030400F8").unwrap();
+        let mut data = vec![0; MEMORY_SIZE];
+        image.place(&mut data).unwrap();
+        assert_eq!(&data[0x0400..0x0403], &[0xa9, 0x42, 0xea]);
+        let image = parse_srecord("S2070104008D000264").unwrap();
+        assert!(image.place(&mut data).is_err());
+    }
+}
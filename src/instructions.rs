@@ -0,0 +1,670 @@
+use crate::addressing::AddressingMode as Mode;
+
+/// The instructions of the 6502
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Adc, And, Asl, Bcc, Bcs, Beq, Bit, Bmi, Bne, Bpl, Brk, Bvc, Bvs, Clc,
+    Cld, Cli, Clv, Cmp, Cpx, Cpy, Dec, Dex, Dey, Eor, Inc, Inx, Iny, Jmp,
+    Jsr, Lda, Ldx, Ldy, Lsr, Nop, Ora, Pha, Php, Pla, Plp, Rol, Ror, Rti,
+    Rts, Sbc, Sec, Sed, Sei, Sta, Stx, Sty, Tax, Tay, Tsx, Txa, Txs, Tya,
+    // Undocumented NMOS instructions
+    Alr, Anc, Arr, Dcp, Isc, Lax, Rla, Rra, Sax, Sbx, Slo, Sre,
+    /// Unstable: the result depends on the chip, these do what most of them do
+    Ane, Las, Lxa, Sha, Shx, Shy, Tas,
+    /// Halts the processor until a reset
+    Jam,
+    // Added by the 65C02
+    Bra, Phx, Phy, Plx, Ply, Stp, Stz, Trb, Tsb, Wai,
+    /// Reset, set, or branch on the reset or set, bit n of a zero page byte
+    Rmb(u8), Smb(u8), Bbr(u8), Bbs(u8),
+    // Added by the 65C816. JML is JMP with a long addressing mode.
+    Brl, Cop, Jsl, Mvn, Mvp, Pea, Pei, Per, Phb, Phd, Phk, Plb, Pld, Rep,
+    Rtl, Sep, Tcd, Tcs, Tdc, Tsc, Txy, Tyx, Wdm, Xba, Xce,
+}
+
+/// How an instruction uses its operand, which decides the bus cycles it goes through
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// Reads the operand and updates registers or flags
+    Read,
+    /// Writes a register to memory
+    Write,
+    /// Reads the operand, modifies it and writes it back
+    ReadModifyWrite,
+    /// Only works on registers and flags, or does nothing
+    Implied,
+    Branch,
+    /// Jumps, subroutine calls, interrupts and stack operations, which
+    /// each have their own sequence
+    Control,
+}
+
+impl Instruction {
+    /// How the instruction uses its operand when it has addressing mode `mode`
+    pub fn access(self, mode: Mode) -> Access {
+        match self {
+            Instruction::Adc | Instruction::And | Instruction::Bit | Instruction::Cmp
+            | Instruction::Cpx | Instruction::Cpy | Instruction::Eor | Instruction::Lda
+            | Instruction::Ldx | Instruction::Ldy | Instruction::Ora | Instruction::Sbc
+            | Instruction::Alr | Instruction::Anc | Instruction::Arr | Instruction::Lax
+            | Instruction::Sbx | Instruction::Ane | Instruction::Las | Instruction::Lxa => Access::Read,
+            // The undocumented NOPs with an operand read it
+            Instruction::Nop if mode != Mode::Implied => Access::Read,
+            Instruction::Sta | Instruction::Stx | Instruction::Sty | Instruction::Sax
+            | Instruction::Sha | Instruction::Shx | Instruction::Shy | Instruction::Tas
+            | Instruction::Stz => Access::Write,
+            Instruction::Asl | Instruction::Dec | Instruction::Inc | Instruction::Lsr
+            | Instruction::Rol | Instruction::Ror | Instruction::Dcp | Instruction::Isc
+            | Instruction::Rla | Instruction::Rra | Instruction::Slo | Instruction::Sre
+            | Instruction::Trb | Instruction::Tsb | Instruction::Rmb(_) | Instruction::Smb(_) => Access::ReadModifyWrite,
+            Instruction::Bcc | Instruction::Bcs | Instruction::Beq | Instruction::Bmi
+            | Instruction::Bne | Instruction::Bpl | Instruction::Bvc | Instruction::Bvs
+            | Instruction::Bra | Instruction::Bbr(_) | Instruction::Bbs(_) => Access::Branch,
+            Instruction::Brk | Instruction::Jmp | Instruction::Jsr | Instruction::Pha
+            | Instruction::Php | Instruction::Pla | Instruction::Plp | Instruction::Rti
+            | Instruction::Rts | Instruction::Phx | Instruction::Phy | Instruction::Plx
+            | Instruction::Ply => Access::Control,
+            _ => Access::Implied,
+        }
+    }
+
+    /// Whether this is one of the undocumented NMOS instructions. The
+    /// undocumented NOPs are left out, they are NOPs either way.
+    pub fn undocumented(self) -> bool {
+        matches!(self,
+            Instruction::Alr | Instruction::Anc | Instruction::Arr | Instruction::Dcp
+            | Instruction::Isc | Instruction::Lax | Instruction::Rla | Instruction::Rra
+            | Instruction::Sax | Instruction::Sbx | Instruction::Slo | Instruction::Sre
+            | Instruction::Ane | Instruction::Las | Instruction::Lxa | Instruction::Sha
+            | Instruction::Shx | Instruction::Shy | Instruction::Tas | Instruction::Jam)
+    }
+
+    /// Lower case mnemonic, as shown in the debug info
+    pub fn name(self) -> String {
+        match self {
+            Instruction::Rmb(bit) => format!("rmb{}", bit),
+            Instruction::Smb(bit) => format!("smb{}", bit),
+            Instruction::Bbr(bit) => format!("bbr{}", bit),
+            Instruction::Bbs(bit) => format!("bbs{}", bit),
+            _ => format!("{:?}", self).to_lowercase(),
+        }
+    }
+}
+
+/// The instruction and addressing mode of an opcode on the NMOS 6502
+pub fn decode(opcode: u8) -> (Instruction, Mode) {
+    match opcode {
+        0x00 => (Instruction::Brk, Mode::Implied),
+        0x01 => (Instruction::Ora, Mode::IndirectX),
+        0x02 => (Instruction::Jam, Mode::Implied),
+        0x03 => (Instruction::Slo, Mode::IndirectX),
+        0x04 => (Instruction::Nop, Mode::ZeroPage),
+        0x05 => (Instruction::Ora, Mode::ZeroPage),
+        0x06 => (Instruction::Asl, Mode::ZeroPage),
+        0x07 => (Instruction::Slo, Mode::ZeroPage),
+        0x08 => (Instruction::Php, Mode::Implied),
+        0x09 => (Instruction::Ora, Mode::Immediate),
+        0x0a => (Instruction::Asl, Mode::Accumulator),
+        0x0b => (Instruction::Anc, Mode::Immediate),
+        0x0c => (Instruction::Nop, Mode::Absolute),
+        0x0d => (Instruction::Ora, Mode::Absolute),
+        0x0e => (Instruction::Asl, Mode::Absolute),
+        0x0f => (Instruction::Slo, Mode::Absolute),
+        0x10 => (Instruction::Bpl, Mode::Relative),
+        0x11 => (Instruction::Ora, Mode::IndirectY),
+        0x12 => (Instruction::Jam, Mode::Implied),
+        0x13 => (Instruction::Slo, Mode::IndirectY),
+        0x14 => (Instruction::Nop, Mode::ZeroPageX),
+        0x15 => (Instruction::Ora, Mode::ZeroPageX),
+        0x16 => (Instruction::Asl, Mode::ZeroPageX),
+        0x17 => (Instruction::Slo, Mode::ZeroPageX),
+        0x18 => (Instruction::Clc, Mode::Implied),
+        0x19 => (Instruction::Ora, Mode::AbsoluteY),
+        0x1a => (Instruction::Nop, Mode::Implied),
+        0x1b => (Instruction::Slo, Mode::AbsoluteY),
+        0x1c => (Instruction::Nop, Mode::AbsoluteX),
+        0x1d => (Instruction::Ora, Mode::AbsoluteX),
+        0x1e => (Instruction::Asl, Mode::AbsoluteX),
+        0x1f => (Instruction::Slo, Mode::AbsoluteX),
+        0x20 => (Instruction::Jsr, Mode::Absolute),
+        0x21 => (Instruction::And, Mode::IndirectX),
+        0x22 => (Instruction::Jam, Mode::Implied),
+        0x23 => (Instruction::Rla, Mode::IndirectX),
+        0x24 => (Instruction::Bit, Mode::ZeroPage),
+        0x25 => (Instruction::And, Mode::ZeroPage),
+        0x26 => (Instruction::Rol, Mode::ZeroPage),
+        0x27 => (Instruction::Rla, Mode::ZeroPage),
+        0x28 => (Instruction::Plp, Mode::Implied),
+        0x29 => (Instruction::And, Mode::Immediate),
+        0x2a => (Instruction::Rol, Mode::Accumulator),
+        0x2b => (Instruction::Anc, Mode::Immediate),
+        0x2c => (Instruction::Bit, Mode::Absolute),
+        0x2d => (Instruction::And, Mode::Absolute),
+        0x2e => (Instruction::Rol, Mode::Absolute),
+        0x2f => (Instruction::Rla, Mode::Absolute),
+        0x30 => (Instruction::Bmi, Mode::Relative),
+        0x31 => (Instruction::And, Mode::IndirectY),
+        0x32 => (Instruction::Jam, Mode::Implied),
+        0x33 => (Instruction::Rla, Mode::IndirectY),
+        0x34 => (Instruction::Nop, Mode::ZeroPageX),
+        0x35 => (Instruction::And, Mode::ZeroPageX),
+        0x36 => (Instruction::Rol, Mode::ZeroPageX),
+        0x37 => (Instruction::Rla, Mode::ZeroPageX),
+        0x38 => (Instruction::Sec, Mode::Implied),
+        0x39 => (Instruction::And, Mode::AbsoluteY),
+        0x3a => (Instruction::Nop, Mode::Implied),
+        0x3b => (Instruction::Rla, Mode::AbsoluteY),
+        0x3c => (Instruction::Nop, Mode::AbsoluteX),
+        0x3d => (Instruction::And, Mode::AbsoluteX),
+        0x3e => (Instruction::Rol, Mode::AbsoluteX),
+        0x3f => (Instruction::Rla, Mode::AbsoluteX),
+        0x40 => (Instruction::Rti, Mode::Implied),
+        0x41 => (Instruction::Eor, Mode::IndirectX),
+        0x42 => (Instruction::Jam, Mode::Implied),
+        0x43 => (Instruction::Sre, Mode::IndirectX),
+        0x44 => (Instruction::Nop, Mode::ZeroPage),
+        0x45 => (Instruction::Eor, Mode::ZeroPage),
+        0x46 => (Instruction::Lsr, Mode::ZeroPage),
+        0x47 => (Instruction::Sre, Mode::ZeroPage),
+        0x48 => (Instruction::Pha, Mode::Implied),
+        0x49 => (Instruction::Eor, Mode::Immediate),
+        0x4a => (Instruction::Lsr, Mode::Accumulator),
+        0x4b => (Instruction::Alr, Mode::Immediate),
+        0x4c => (Instruction::Jmp, Mode::Absolute),
+        0x4d => (Instruction::Eor, Mode::Absolute),
+        0x4e => (Instruction::Lsr, Mode::Absolute),
+        0x4f => (Instruction::Sre, Mode::Absolute),
+        0x50 => (Instruction::Bvc, Mode::Relative),
+        0x51 => (Instruction::Eor, Mode::IndirectY),
+        0x52 => (Instruction::Jam, Mode::Implied),
+        0x53 => (Instruction::Sre, Mode::IndirectY),
+        0x54 => (Instruction::Nop, Mode::ZeroPageX),
+        0x55 => (Instruction::Eor, Mode::ZeroPageX),
+        0x56 => (Instruction::Lsr, Mode::ZeroPageX),
+        0x57 => (Instruction::Sre, Mode::ZeroPageX),
+        0x58 => (Instruction::Cli, Mode::Implied),
+        0x59 => (Instruction::Eor, Mode::AbsoluteY),
+        0x5a => (Instruction::Nop, Mode::Implied),
+        0x5b => (Instruction::Sre, Mode::AbsoluteY),
+        0x5c => (Instruction::Nop, Mode::AbsoluteX),
+        0x5d => (Instruction::Eor, Mode::AbsoluteX),
+        0x5e => (Instruction::Lsr, Mode::AbsoluteX),
+        0x5f => (Instruction::Sre, Mode::AbsoluteX),
+        0x60 => (Instruction::Rts, Mode::Implied),
+        0x61 => (Instruction::Adc, Mode::IndirectX),
+        0x62 => (Instruction::Jam, Mode::Implied),
+        0x63 => (Instruction::Rra, Mode::IndirectX),
+        0x64 => (Instruction::Nop, Mode::ZeroPage),
+        0x65 => (Instruction::Adc, Mode::ZeroPage),
+        0x66 => (Instruction::Ror, Mode::ZeroPage),
+        0x67 => (Instruction::Rra, Mode::ZeroPage),
+        0x68 => (Instruction::Pla, Mode::Implied),
+        0x69 => (Instruction::Adc, Mode::Immediate),
+        0x6a => (Instruction::Ror, Mode::Accumulator),
+        0x6b => (Instruction::Arr, Mode::Immediate),
+        0x6c => (Instruction::Jmp, Mode::Indirect),
+        0x6d => (Instruction::Adc, Mode::Absolute),
+        0x6e => (Instruction::Ror, Mode::Absolute),
+        0x6f => (Instruction::Rra, Mode::Absolute),
+        0x70 => (Instruction::Bvs, Mode::Relative),
+        0x71 => (Instruction::Adc, Mode::IndirectY),
+        0x72 => (Instruction::Jam, Mode::Implied),
+        0x73 => (Instruction::Rra, Mode::IndirectY),
+        0x74 => (Instruction::Nop, Mode::ZeroPageX),
+        0x75 => (Instruction::Adc, Mode::ZeroPageX),
+        0x76 => (Instruction::Ror, Mode::ZeroPageX),
+        0x77 => (Instruction::Rra, Mode::ZeroPageX),
+        0x78 => (Instruction::Sei, Mode::Implied),
+        0x79 => (Instruction::Adc, Mode::AbsoluteY),
+        0x7a => (Instruction::Nop, Mode::Implied),
+        0x7b => (Instruction::Rra, Mode::AbsoluteY),
+        0x7c => (Instruction::Nop, Mode::AbsoluteX),
+        0x7d => (Instruction::Adc, Mode::AbsoluteX),
+        0x7e => (Instruction::Ror, Mode::AbsoluteX),
+        0x7f => (Instruction::Rra, Mode::AbsoluteX),
+        0x80 => (Instruction::Nop, Mode::Immediate),
+        0x81 => (Instruction::Sta, Mode::IndirectX),
+        0x82 => (Instruction::Nop, Mode::Immediate),
+        0x83 => (Instruction::Sax, Mode::IndirectX),
+        0x84 => (Instruction::Sty, Mode::ZeroPage),
+        0x85 => (Instruction::Sta, Mode::ZeroPage),
+        0x86 => (Instruction::Stx, Mode::ZeroPage),
+        0x87 => (Instruction::Sax, Mode::ZeroPage),
+        0x88 => (Instruction::Dey, Mode::Implied),
+        0x89 => (Instruction::Nop, Mode::Immediate),
+        0x8a => (Instruction::Txa, Mode::Implied),
+        0x8b => (Instruction::Ane, Mode::Immediate),
+        0x8c => (Instruction::Sty, Mode::Absolute),
+        0x8d => (Instruction::Sta, Mode::Absolute),
+        0x8e => (Instruction::Stx, Mode::Absolute),
+        0x8f => (Instruction::Sax, Mode::Absolute),
+        0x90 => (Instruction::Bcc, Mode::Relative),
+        0x91 => (Instruction::Sta, Mode::IndirectY),
+        0x92 => (Instruction::Jam, Mode::Implied),
+        0x93 => (Instruction::Sha, Mode::IndirectY),
+        0x94 => (Instruction::Sty, Mode::ZeroPageX),
+        0x95 => (Instruction::Sta, Mode::ZeroPageX),
+        0x96 => (Instruction::Stx, Mode::ZeroPageY),
+        0x97 => (Instruction::Sax, Mode::ZeroPageY),
+        0x98 => (Instruction::Tya, Mode::Implied),
+        0x99 => (Instruction::Sta, Mode::AbsoluteY),
+        0x9a => (Instruction::Txs, Mode::Implied),
+        0x9b => (Instruction::Tas, Mode::AbsoluteY),
+        0x9c => (Instruction::Shy, Mode::AbsoluteX),
+        0x9d => (Instruction::Sta, Mode::AbsoluteX),
+        0x9e => (Instruction::Shx, Mode::AbsoluteY),
+        0x9f => (Instruction::Sha, Mode::AbsoluteY),
+        0xa0 => (Instruction::Ldy, Mode::Immediate),
+        0xa1 => (Instruction::Lda, Mode::IndirectX),
+        0xa2 => (Instruction::Ldx, Mode::Immediate),
+        0xa3 => (Instruction::Lax, Mode::IndirectX),
+        0xa4 => (Instruction::Ldy, Mode::ZeroPage),
+        0xa5 => (Instruction::Lda, Mode::ZeroPage),
+        0xa6 => (Instruction::Ldx, Mode::ZeroPage),
+        0xa7 => (Instruction::Lax, Mode::ZeroPage),
+        0xa8 => (Instruction::Tay, Mode::Implied),
+        0xa9 => (Instruction::Lda, Mode::Immediate),
+        0xaa => (Instruction::Tax, Mode::Implied),
+        0xab => (Instruction::Lxa, Mode::Immediate),
+        0xac => (Instruction::Ldy, Mode::Absolute),
+        0xad => (Instruction::Lda, Mode::Absolute),
+        0xae => (Instruction::Ldx, Mode::Absolute),
+        0xaf => (Instruction::Lax, Mode::Absolute),
+        0xb0 => (Instruction::Bcs, Mode::Relative),
+        0xb1 => (Instruction::Lda, Mode::IndirectY),
+        0xb2 => (Instruction::Jam, Mode::Implied),
+        0xb3 => (Instruction::Lax, Mode::IndirectY),
+        0xb4 => (Instruction::Ldy, Mode::ZeroPageX),
+        0xb5 => (Instruction::Lda, Mode::ZeroPageX),
+        0xb6 => (Instruction::Ldx, Mode::ZeroPageY),
+        0xb7 => (Instruction::Lax, Mode::ZeroPageY),
+        0xb8 => (Instruction::Clv, Mode::Implied),
+        0xb9 => (Instruction::Lda, Mode::AbsoluteY),
+        0xba => (Instruction::Tsx, Mode::Implied),
+        0xbb => (Instruction::Las, Mode::AbsoluteY),
+        0xbc => (Instruction::Ldy, Mode::AbsoluteX),
+        0xbd => (Instruction::Lda, Mode::AbsoluteX),
+        0xbe => (Instruction::Ldx, Mode::AbsoluteY),
+        0xbf => (Instruction::Lax, Mode::AbsoluteY),
+        0xc0 => (Instruction::Cpy, Mode::Immediate),
+        0xc1 => (Instruction::Cmp, Mode::IndirectX),
+        0xc2 => (Instruction::Nop, Mode::Immediate),
+        0xc3 => (Instruction::Dcp, Mode::IndirectX),
+        0xc4 => (Instruction::Cpy, Mode::ZeroPage),
+        0xc5 => (Instruction::Cmp, Mode::ZeroPage),
+        0xc6 => (Instruction::Dec, Mode::ZeroPage),
+        0xc7 => (Instruction::Dcp, Mode::ZeroPage),
+        0xc8 => (Instruction::Iny, Mode::Implied),
+        0xc9 => (Instruction::Cmp, Mode::Immediate),
+        0xca => (Instruction::Dex, Mode::Implied),
+        0xcb => (Instruction::Sbx, Mode::Immediate),
+        0xcc => (Instruction::Cpy, Mode::Absolute),
+        0xcd => (Instruction::Cmp, Mode::Absolute),
+        0xce => (Instruction::Dec, Mode::Absolute),
+        0xcf => (Instruction::Dcp, Mode::Absolute),
+        0xd0 => (Instruction::Bne, Mode::Relative),
+        0xd1 => (Instruction::Cmp, Mode::IndirectY),
+        0xd2 => (Instruction::Jam, Mode::Implied),
+        0xd3 => (Instruction::Dcp, Mode::IndirectY),
+        0xd4 => (Instruction::Nop, Mode::ZeroPageX),
+        0xd5 => (Instruction::Cmp, Mode::ZeroPageX),
+        0xd6 => (Instruction::Dec, Mode::ZeroPageX),
+        0xd7 => (Instruction::Dcp, Mode::ZeroPageX),
+        0xd8 => (Instruction::Cld, Mode::Implied),
+        0xd9 => (Instruction::Cmp, Mode::AbsoluteY),
+        0xda => (Instruction::Nop, Mode::Implied),
+        0xdb => (Instruction::Dcp, Mode::AbsoluteY),
+        0xdc => (Instruction::Nop, Mode::AbsoluteX),
+        0xdd => (Instruction::Cmp, Mode::AbsoluteX),
+        0xde => (Instruction::Dec, Mode::AbsoluteX),
+        0xdf => (Instruction::Dcp, Mode::AbsoluteX),
+        0xe0 => (Instruction::Cpx, Mode::Immediate),
+        0xe1 => (Instruction::Sbc, Mode::IndirectX),
+        0xe2 => (Instruction::Nop, Mode::Immediate),
+        0xe3 => (Instruction::Isc, Mode::IndirectX),
+        0xe4 => (Instruction::Cpx, Mode::ZeroPage),
+        0xe5 => (Instruction::Sbc, Mode::ZeroPage),
+        0xe6 => (Instruction::Inc, Mode::ZeroPage),
+        0xe7 => (Instruction::Isc, Mode::ZeroPage),
+        0xe8 => (Instruction::Inx, Mode::Implied),
+        0xe9 => (Instruction::Sbc, Mode::Immediate),
+        0xea => (Instruction::Nop, Mode::Implied),
+        0xeb => (Instruction::Sbc, Mode::Immediate),
+        0xec => (Instruction::Cpx, Mode::Absolute),
+        0xed => (Instruction::Sbc, Mode::Absolute),
+        0xee => (Instruction::Inc, Mode::Absolute),
+        0xef => (Instruction::Isc, Mode::Absolute),
+        0xf0 => (Instruction::Beq, Mode::Relative),
+        0xf1 => (Instruction::Sbc, Mode::IndirectY),
+        0xf2 => (Instruction::Jam, Mode::Implied),
+        0xf3 => (Instruction::Isc, Mode::IndirectY),
+        0xf4 => (Instruction::Nop, Mode::ZeroPageX),
+        0xf5 => (Instruction::Sbc, Mode::ZeroPageX),
+        0xf6 => (Instruction::Inc, Mode::ZeroPageX),
+        0xf7 => (Instruction::Isc, Mode::ZeroPageX),
+        0xf8 => (Instruction::Sed, Mode::Implied),
+        0xf9 => (Instruction::Sbc, Mode::AbsoluteY),
+        0xfa => (Instruction::Nop, Mode::Implied),
+        0xfb => (Instruction::Isc, Mode::AbsoluteY),
+        0xfc => (Instruction::Nop, Mode::AbsoluteX),
+        0xfd => (Instruction::Sbc, Mode::AbsoluteX),
+        0xfe => (Instruction::Inc, Mode::AbsoluteX),
+        0xff => (Instruction::Isc, Mode::AbsoluteX),
+    }
+}
+
+/// The instruction and addressing mode of an opcode on the 65C02, with the
+/// Rockwell and WDC bit instructions. The opcodes it does not define are
+/// NOPs taking the same number of bytes as on the NMOS chip.
+pub fn decode_65c02(opcode: u8) -> (Instruction, Mode) {
+    match opcode {
+        0x04 => (Instruction::Tsb, Mode::ZeroPage),
+        0x0c => (Instruction::Tsb, Mode::Absolute),
+        0x12 => (Instruction::Ora, Mode::ZeroPageIndirect),
+        0x14 => (Instruction::Trb, Mode::ZeroPage),
+        0x1a => (Instruction::Inc, Mode::Accumulator),
+        0x1c => (Instruction::Trb, Mode::Absolute),
+        0x32 => (Instruction::And, Mode::ZeroPageIndirect),
+        0x34 => (Instruction::Bit, Mode::ZeroPageX),
+        0x3a => (Instruction::Dec, Mode::Accumulator),
+        0x3c => (Instruction::Bit, Mode::AbsoluteX),
+        0x52 => (Instruction::Eor, Mode::ZeroPageIndirect),
+        0x5a => (Instruction::Phy, Mode::Implied),
+        0x64 => (Instruction::Stz, Mode::ZeroPage),
+        0x72 => (Instruction::Adc, Mode::ZeroPageIndirect),
+        0x74 => (Instruction::Stz, Mode::ZeroPageX),
+        0x7a => (Instruction::Ply, Mode::Implied),
+        0x7c => (Instruction::Jmp, Mode::AbsoluteIndirectX),
+        0x80 => (Instruction::Bra, Mode::Relative),
+        0x89 => (Instruction::Bit, Mode::Immediate),
+        0x92 => (Instruction::Sta, Mode::ZeroPageIndirect),
+        0x9c => (Instruction::Stz, Mode::Absolute),
+        0x9e => (Instruction::Stz, Mode::AbsoluteX),
+        0xb2 => (Instruction::Lda, Mode::ZeroPageIndirect),
+        0xcb => (Instruction::Wai, Mode::Implied),
+        0xd2 => (Instruction::Cmp, Mode::ZeroPageIndirect),
+        0xda => (Instruction::Phx, Mode::Implied),
+        0xdb => (Instruction::Stp, Mode::Implied),
+        0xf2 => (Instruction::Sbc, Mode::ZeroPageIndirect),
+        0xfa => (Instruction::Plx, Mode::Implied),
+
+        // Bit n of the instruction is in the high nibble of the opcode
+        _ if opcode & 0x8f == 0x07 => (Instruction::Rmb(opcode >> 4), Mode::ZeroPage),
+        _ if opcode & 0x8f == 0x87 => (Instruction::Smb((opcode >> 4) & 0x07), Mode::ZeroPage),
+        _ if opcode & 0x8f == 0x0f => (Instruction::Bbr(opcode >> 4), Mode::ZeroPageRelative),
+        _ if opcode & 0x8f == 0x8f => (Instruction::Bbs((opcode >> 4) & 0x07), Mode::ZeroPageRelative),
+
+        // Undefined, the addressing mode only decides how many bytes they
+        // take and what they read
+        0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xc2 | 0xe2 => (Instruction::Nop, Mode::Immediate),
+        0x44 => (Instruction::Nop, Mode::ZeroPage),
+        0x54 | 0xd4 | 0xf4 => (Instruction::Nop, Mode::ZeroPageX),
+        0x5c | 0xdc | 0xfc => (Instruction::Nop, Mode::Absolute),
+        _ if opcode & 0x03 == 0x03 => (Instruction::Nop, Mode::Implied),
+
+        _ => decode(opcode),
+    }
+}
+
+/// The instruction and addressing mode of an opcode on the 65C816. There
+/// is no undefined opcode left, and no Rockwell bit instruction either.
+pub fn decode_65c816(opcode: u8) -> (Instruction, Mode) {
+    match opcode {
+        0x00 => (Instruction::Brk, Mode::Implied),
+        0x01 => (Instruction::Ora, Mode::IndirectX),
+        0x02 => (Instruction::Cop, Mode::Implied),
+        0x03 => (Instruction::Ora, Mode::StackRelative),
+        0x04 => (Instruction::Tsb, Mode::ZeroPage),
+        0x05 => (Instruction::Ora, Mode::ZeroPage),
+        0x06 => (Instruction::Asl, Mode::ZeroPage),
+        0x07 => (Instruction::Ora, Mode::ZeroPageIndirectLong),
+        0x08 => (Instruction::Php, Mode::Implied),
+        0x09 => (Instruction::Ora, Mode::Immediate),
+        0x0a => (Instruction::Asl, Mode::Accumulator),
+        0x0b => (Instruction::Phd, Mode::Implied),
+        0x0c => (Instruction::Tsb, Mode::Absolute),
+        0x0d => (Instruction::Ora, Mode::Absolute),
+        0x0e => (Instruction::Asl, Mode::Absolute),
+        0x0f => (Instruction::Ora, Mode::AbsoluteLong),
+        0x10 => (Instruction::Bpl, Mode::Relative),
+        0x11 => (Instruction::Ora, Mode::IndirectY),
+        0x12 => (Instruction::Ora, Mode::ZeroPageIndirect),
+        0x13 => (Instruction::Ora, Mode::StackRelativeIndirectY),
+        0x14 => (Instruction::Trb, Mode::ZeroPage),
+        0x15 => (Instruction::Ora, Mode::ZeroPageX),
+        0x16 => (Instruction::Asl, Mode::ZeroPageX),
+        0x17 => (Instruction::Ora, Mode::ZeroPageIndirectLongY),
+        0x18 => (Instruction::Clc, Mode::Implied),
+        0x19 => (Instruction::Ora, Mode::AbsoluteY),
+        0x1a => (Instruction::Inc, Mode::Accumulator),
+        0x1b => (Instruction::Tcs, Mode::Implied),
+        0x1c => (Instruction::Trb, Mode::Absolute),
+        0x1d => (Instruction::Ora, Mode::AbsoluteX),
+        0x1e => (Instruction::Asl, Mode::AbsoluteX),
+        0x1f => (Instruction::Ora, Mode::AbsoluteLongX),
+        0x20 => (Instruction::Jsr, Mode::Absolute),
+        0x21 => (Instruction::And, Mode::IndirectX),
+        0x22 => (Instruction::Jsl, Mode::AbsoluteLong),
+        0x23 => (Instruction::And, Mode::StackRelative),
+        0x24 => (Instruction::Bit, Mode::ZeroPage),
+        0x25 => (Instruction::And, Mode::ZeroPage),
+        0x26 => (Instruction::Rol, Mode::ZeroPage),
+        0x27 => (Instruction::And, Mode::ZeroPageIndirectLong),
+        0x28 => (Instruction::Plp, Mode::Implied),
+        0x29 => (Instruction::And, Mode::Immediate),
+        0x2a => (Instruction::Rol, Mode::Accumulator),
+        0x2b => (Instruction::Pld, Mode::Implied),
+        0x2c => (Instruction::Bit, Mode::Absolute),
+        0x2d => (Instruction::And, Mode::Absolute),
+        0x2e => (Instruction::Rol, Mode::Absolute),
+        0x2f => (Instruction::And, Mode::AbsoluteLong),
+        0x30 => (Instruction::Bmi, Mode::Relative),
+        0x31 => (Instruction::And, Mode::IndirectY),
+        0x32 => (Instruction::And, Mode::ZeroPageIndirect),
+        0x33 => (Instruction::And, Mode::StackRelativeIndirectY),
+        0x34 => (Instruction::Bit, Mode::ZeroPageX),
+        0x35 => (Instruction::And, Mode::ZeroPageX),
+        0x36 => (Instruction::Rol, Mode::ZeroPageX),
+        0x37 => (Instruction::And, Mode::ZeroPageIndirectLongY),
+        0x38 => (Instruction::Sec, Mode::Implied),
+        0x39 => (Instruction::And, Mode::AbsoluteY),
+        0x3a => (Instruction::Dec, Mode::Accumulator),
+        0x3b => (Instruction::Tsc, Mode::Implied),
+        0x3c => (Instruction::Bit, Mode::AbsoluteX),
+        0x3d => (Instruction::And, Mode::AbsoluteX),
+        0x3e => (Instruction::Rol, Mode::AbsoluteX),
+        0x3f => (Instruction::And, Mode::AbsoluteLongX),
+        0x40 => (Instruction::Rti, Mode::Implied),
+        0x41 => (Instruction::Eor, Mode::IndirectX),
+        0x42 => (Instruction::Wdm, Mode::Immediate),
+        0x43 => (Instruction::Eor, Mode::StackRelative),
+        0x44 => (Instruction::Mvp, Mode::BlockMove),
+        0x45 => (Instruction::Eor, Mode::ZeroPage),
+        0x46 => (Instruction::Lsr, Mode::ZeroPage),
+        0x47 => (Instruction::Eor, Mode::ZeroPageIndirectLong),
+        0x48 => (Instruction::Pha, Mode::Implied),
+        0x49 => (Instruction::Eor, Mode::Immediate),
+        0x4a => (Instruction::Lsr, Mode::Accumulator),
+        0x4b => (Instruction::Phk, Mode::Implied),
+        0x4c => (Instruction::Jmp, Mode::Absolute),
+        0x4d => (Instruction::Eor, Mode::Absolute),
+        0x4e => (Instruction::Lsr, Mode::Absolute),
+        0x4f => (Instruction::Eor, Mode::AbsoluteLong),
+        0x50 => (Instruction::Bvc, Mode::Relative),
+        0x51 => (Instruction::Eor, Mode::IndirectY),
+        0x52 => (Instruction::Eor, Mode::ZeroPageIndirect),
+        0x53 => (Instruction::Eor, Mode::StackRelativeIndirectY),
+        0x54 => (Instruction::Mvn, Mode::BlockMove),
+        0x55 => (Instruction::Eor, Mode::ZeroPageX),
+        0x56 => (Instruction::Lsr, Mode::ZeroPageX),
+        0x57 => (Instruction::Eor, Mode::ZeroPageIndirectLongY),
+        0x58 => (Instruction::Cli, Mode::Implied),
+        0x59 => (Instruction::Eor, Mode::AbsoluteY),
+        0x5a => (Instruction::Phy, Mode::Implied),
+        0x5b => (Instruction::Tcd, Mode::Implied),
+        0x5c => (Instruction::Jmp, Mode::AbsoluteLong),
+        0x5d => (Instruction::Eor, Mode::AbsoluteX),
+        0x5e => (Instruction::Lsr, Mode::AbsoluteX),
+        0x5f => (Instruction::Eor, Mode::AbsoluteLongX),
+        0x60 => (Instruction::Rts, Mode::Implied),
+        0x61 => (Instruction::Adc, Mode::IndirectX),
+        0x62 => (Instruction::Per, Mode::RelativeLong),
+        0x63 => (Instruction::Adc, Mode::StackRelative),
+        0x64 => (Instruction::Stz, Mode::ZeroPage),
+        0x65 => (Instruction::Adc, Mode::ZeroPage),
+        0x66 => (Instruction::Ror, Mode::ZeroPage),
+        0x67 => (Instruction::Adc, Mode::ZeroPageIndirectLong),
+        0x68 => (Instruction::Pla, Mode::Implied),
+        0x69 => (Instruction::Adc, Mode::Immediate),
+        0x6a => (Instruction::Ror, Mode::Accumulator),
+        0x6b => (Instruction::Rtl, Mode::Implied),
+        0x6c => (Instruction::Jmp, Mode::Indirect),
+        0x6d => (Instruction::Adc, Mode::Absolute),
+        0x6e => (Instruction::Ror, Mode::Absolute),
+        0x6f => (Instruction::Adc, Mode::AbsoluteLong),
+        0x70 => (Instruction::Bvs, Mode::Relative),
+        0x71 => (Instruction::Adc, Mode::IndirectY),
+        0x72 => (Instruction::Adc, Mode::ZeroPageIndirect),
+        0x73 => (Instruction::Adc, Mode::StackRelativeIndirectY),
+        0x74 => (Instruction::Stz, Mode::ZeroPageX),
+        0x75 => (Instruction::Adc, Mode::ZeroPageX),
+        0x76 => (Instruction::Ror, Mode::ZeroPageX),
+        0x77 => (Instruction::Adc, Mode::ZeroPageIndirectLongY),
+        0x78 => (Instruction::Sei, Mode::Implied),
+        0x79 => (Instruction::Adc, Mode::AbsoluteY),
+        0x7a => (Instruction::Ply, Mode::Implied),
+        0x7b => (Instruction::Tdc, Mode::Implied),
+        0x7c => (Instruction::Jmp, Mode::AbsoluteIndirectX),
+        0x7d => (Instruction::Adc, Mode::AbsoluteX),
+        0x7e => (Instruction::Ror, Mode::AbsoluteX),
+        0x7f => (Instruction::Adc, Mode::AbsoluteLongX),
+        0x80 => (Instruction::Bra, Mode::Relative),
+        0x81 => (Instruction::Sta, Mode::IndirectX),
+        0x82 => (Instruction::Brl, Mode::RelativeLong),
+        0x83 => (Instruction::Sta, Mode::StackRelative),
+        0x84 => (Instruction::Sty, Mode::ZeroPage),
+        0x85 => (Instruction::Sta, Mode::ZeroPage),
+        0x86 => (Instruction::Stx, Mode::ZeroPage),
+        0x87 => (Instruction::Sta, Mode::ZeroPageIndirectLong),
+        0x88 => (Instruction::Dey, Mode::Implied),
+        0x89 => (Instruction::Bit, Mode::Immediate),
+        0x8a => (Instruction::Txa, Mode::Implied),
+        0x8b => (Instruction::Phb, Mode::Implied),
+        0x8c => (Instruction::Sty, Mode::Absolute),
+        0x8d => (Instruction::Sta, Mode::Absolute),
+        0x8e => (Instruction::Stx, Mode::Absolute),
+        0x8f => (Instruction::Sta, Mode::AbsoluteLong),
+        0x90 => (Instruction::Bcc, Mode::Relative),
+        0x91 => (Instruction::Sta, Mode::IndirectY),
+        0x92 => (Instruction::Sta, Mode::ZeroPageIndirect),
+        0x93 => (Instruction::Sta, Mode::StackRelativeIndirectY),
+        0x94 => (Instruction::Sty, Mode::ZeroPageX),
+        0x95 => (Instruction::Sta, Mode::ZeroPageX),
+        0x96 => (Instruction::Stx, Mode::ZeroPageY),
+        0x97 => (Instruction::Sta, Mode::ZeroPageIndirectLongY),
+        0x98 => (Instruction::Tya, Mode::Implied),
+        0x99 => (Instruction::Sta, Mode::AbsoluteY),
+        0x9a => (Instruction::Txs, Mode::Implied),
+        0x9b => (Instruction::Txy, Mode::Implied),
+        0x9c => (Instruction::Stz, Mode::Absolute),
+        0x9d => (Instruction::Sta, Mode::AbsoluteX),
+        0x9e => (Instruction::Stz, Mode::AbsoluteX),
+        0x9f => (Instruction::Sta, Mode::AbsoluteLongX),
+        0xa0 => (Instruction::Ldy, Mode::Immediate),
+        0xa1 => (Instruction::Lda, Mode::IndirectX),
+        0xa2 => (Instruction::Ldx, Mode::Immediate),
+        0xa3 => (Instruction::Lda, Mode::StackRelative),
+        0xa4 => (Instruction::Ldy, Mode::ZeroPage),
+        0xa5 => (Instruction::Lda, Mode::ZeroPage),
+        0xa6 => (Instruction::Ldx, Mode::ZeroPage),
+        0xa7 => (Instruction::Lda, Mode::ZeroPageIndirectLong),
+        0xa8 => (Instruction::Tay, Mode::Implied),
+        0xa9 => (Instruction::Lda, Mode::Immediate),
+        0xaa => (Instruction::Tax, Mode::Implied),
+        0xab => (Instruction::Plb, Mode::Implied),
+        0xac => (Instruction::Ldy, Mode::Absolute),
+        0xad => (Instruction::Lda, Mode::Absolute),
+        0xae => (Instruction::Ldx, Mode::Absolute),
+        0xaf => (Instruction::Lda, Mode::AbsoluteLong),
+        0xb0 => (Instruction::Bcs, Mode::Relative),
+        0xb1 => (Instruction::Lda, Mode::IndirectY),
+        0xb2 => (Instruction::Lda, Mode::ZeroPageIndirect),
+        0xb3 => (Instruction::Lda, Mode::StackRelativeIndirectY),
+        0xb4 => (Instruction::Ldy, Mode::ZeroPageX),
+        0xb5 => (Instruction::Lda, Mode::ZeroPageX),
+        0xb6 => (Instruction::Ldx, Mode::ZeroPageY),
+        0xb7 => (Instruction::Lda, Mode::ZeroPageIndirectLongY),
+        0xb8 => (Instruction::Clv, Mode::Implied),
+        0xb9 => (Instruction::Lda, Mode::AbsoluteY),
+        0xba => (Instruction::Tsx, Mode::Implied),
+        0xbb => (Instruction::Tyx, Mode::Implied),
+        0xbc => (Instruction::Ldy, Mode::AbsoluteX),
+        0xbd => (Instruction::Lda, Mode::AbsoluteX),
+        0xbe => (Instruction::Ldx, Mode::AbsoluteY),
+        0xbf => (Instruction::Lda, Mode::AbsoluteLongX),
+        0xc0 => (Instruction::Cpy, Mode::Immediate),
+        0xc1 => (Instruction::Cmp, Mode::IndirectX),
+        0xc2 => (Instruction::Rep, Mode::Immediate),
+        0xc3 => (Instruction::Cmp, Mode::StackRelative),
+        0xc4 => (Instruction::Cpy, Mode::ZeroPage),
+        0xc5 => (Instruction::Cmp, Mode::ZeroPage),
+        0xc6 => (Instruction::Dec, Mode::ZeroPage),
+        0xc7 => (Instruction::Cmp, Mode::ZeroPageIndirectLong),
+        0xc8 => (Instruction::Iny, Mode::Implied),
+        0xc9 => (Instruction::Cmp, Mode::Immediate),
+        0xca => (Instruction::Dex, Mode::Implied),
+        0xcb => (Instruction::Wai, Mode::Implied),
+        0xcc => (Instruction::Cpy, Mode::Absolute),
+        0xcd => (Instruction::Cmp, Mode::Absolute),
+        0xce => (Instruction::Dec, Mode::Absolute),
+        0xcf => (Instruction::Cmp, Mode::AbsoluteLong),
+        0xd0 => (Instruction::Bne, Mode::Relative),
+        0xd1 => (Instruction::Cmp, Mode::IndirectY),
+        0xd2 => (Instruction::Cmp, Mode::ZeroPageIndirect),
+        0xd3 => (Instruction::Cmp, Mode::StackRelativeIndirectY),
+        0xd4 => (Instruction::Pei, Mode::ZeroPageIndirect),
+        0xd5 => (Instruction::Cmp, Mode::ZeroPageX),
+        0xd6 => (Instruction::Dec, Mode::ZeroPageX),
+        0xd7 => (Instruction::Cmp, Mode::ZeroPageIndirectLongY),
+        0xd8 => (Instruction::Cld, Mode::Implied),
+        0xd9 => (Instruction::Cmp, Mode::AbsoluteY),
+        0xda => (Instruction::Phx, Mode::Implied),
+        0xdb => (Instruction::Stp, Mode::Implied),
+        0xdc => (Instruction::Jmp, Mode::AbsoluteIndirectLong),
+        0xdd => (Instruction::Cmp, Mode::AbsoluteX),
+        0xde => (Instruction::Dec, Mode::AbsoluteX),
+        0xdf => (Instruction::Cmp, Mode::AbsoluteLongX),
+        0xe0 => (Instruction::Cpx, Mode::Immediate),
+        0xe1 => (Instruction::Sbc, Mode::IndirectX),
+        0xe2 => (Instruction::Sep, Mode::Immediate),
+        0xe3 => (Instruction::Sbc, Mode::StackRelative),
+        0xe4 => (Instruction::Cpx, Mode::ZeroPage),
+        0xe5 => (Instruction::Sbc, Mode::ZeroPage),
+        0xe6 => (Instruction::Inc, Mode::ZeroPage),
+        0xe7 => (Instruction::Sbc, Mode::ZeroPageIndirectLong),
+        0xe8 => (Instruction::Inx, Mode::Implied),
+        0xe9 => (Instruction::Sbc, Mode::Immediate),
+        0xea => (Instruction::Nop, Mode::Implied),
+        0xeb => (Instruction::Xba, Mode::Implied),
+        0xec => (Instruction::Cpx, Mode::Absolute),
+        0xed => (Instruction::Sbc, Mode::Absolute),
+        0xee => (Instruction::Inc, Mode::Absolute),
+        0xef => (Instruction::Sbc, Mode::AbsoluteLong),
+        0xf0 => (Instruction::Beq, Mode::Relative),
+        0xf1 => (Instruction::Sbc, Mode::IndirectY),
+        0xf2 => (Instruction::Sbc, Mode::ZeroPageIndirect),
+        0xf3 => (Instruction::Sbc, Mode::StackRelativeIndirectY),
+        0xf4 => (Instruction::Pea, Mode::Absolute),
+        0xf5 => (Instruction::Sbc, Mode::ZeroPageX),
+        0xf6 => (Instruction::Inc, Mode::ZeroPageX),
+        0xf7 => (Instruction::Sbc, Mode::ZeroPageIndirectLongY),
+        0xf8 => (Instruction::Sed, Mode::Implied),
+        0xf9 => (Instruction::Sbc, Mode::AbsoluteY),
+        0xfa => (Instruction::Plx, Mode::Implied),
+        0xfb => (Instruction::Xce, Mode::Implied),
+        0xfc => (Instruction::Jsr, Mode::AbsoluteIndirectX),
+        0xfd => (Instruction::Sbc, Mode::AbsoluteX),
+        0xfe => (Instruction::Inc, Mode::AbsoluteX),
+        0xff => (Instruction::Sbc, Mode::AbsoluteLongX),
+    }
+}
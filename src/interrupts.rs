@@ -0,0 +1,88 @@
+/// Address of the NMI handler
+pub const NMI_VECTOR: u16 = 0xfffa;
+/// Address of the first instruction to run after a reset
+pub const RESET_VECTOR: u16 = 0xfffc;
+/// Address of the IRQ and BRK handler
+pub const IRQ_VECTOR: u16 = 0xfffe;
+
+/// The interrupt inputs of the processor.
+///
+/// IRQ is level triggered: it is serviced as long as it is asserted and the
+/// I flag is clear. NMI is edge triggered: asserting it latches one NMI, it
+/// must be released and asserted again to get another one.
+///
+/// The lines are wired-OR: they are driven from outside the board and by
+/// the devices on the bus, and asserted when any of them asserts them.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptLines {
+    irq: bool,
+    nmi: bool,
+    device_irq: bool,
+    device_nmi: bool,
+    nmi_pending: bool,
+    reset_pending: bool,
+}
+
+impl InterruptLines {
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    pub fn set_nmi(&mut self, asserted: bool) {
+        let was_asserted = self.nmi();
+        self.nmi = asserted;
+        self.latch_nmi(was_asserted);
+    }
+
+    /// What the devices on the bus drive the lines to
+    pub fn set_device_lines(&mut self, irq: bool, nmi: bool) {
+        let was_asserted = self.nmi();
+        self.device_irq = irq;
+        self.device_nmi = nmi;
+        self.latch_nmi(was_asserted);
+    }
+
+    fn latch_nmi(&mut self, was_asserted: bool) {
+        if self.nmi() && !was_asserted {
+            self.nmi_pending = true;
+        }
+    }
+
+    /// Reset the processor before the next instruction
+    pub fn reset(&mut self) {
+        self.reset_pending = true;
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq || self.device_irq
+    }
+
+    pub fn nmi(&self) -> bool {
+        self.nmi || self.device_nmi
+    }
+
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    pub fn reset_pending(&self) -> bool {
+        self.reset_pending
+    }
+
+    /// Acknowledge the latched NMI, returns whether there was one
+    pub fn take_nmi(&mut self) -> bool {
+        let pending = self.nmi_pending;
+        self.nmi_pending = false;
+        pending
+    }
+
+    /// Acknowledge a reset request, which also drops any latched NMI
+    pub fn take_reset(&mut self) -> bool {
+        let pending = self.reset_pending;
+        if pending {
+            self.reset_pending = false;
+            self.nmi_pending = false;
+        }
+        pending
+    }
+}
@@ -0,0 +1,226 @@
+//! ld65 linker configurations, so the emulator sees the machine the way the
+//! linker does. Only the MEMORY block matters here: its areas become the
+//! regions of the memory map, and the ones written to the output file say
+//! where the parts of the image go.
+
+use crate::computer::{place_image, MEMORY_SIZE};
+use crate::memory_map::{MemoryMap, Region, RegionType};
+
+/// A MEMORY area of the configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryArea {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    pub read_only: bool,
+    /// Whether the linker pads the area to its full size in the output file
+    pub fill: bool,
+    pub fillval: u8,
+    /// Whether the area goes to the output file, as it does unless it has
+    /// `file = ""`
+    pub in_output: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub areas: Vec<MemoryArea>,
+}
+
+impl Config {
+    /// Parse the MEMORY block of a linker configuration, the other blocks are skipped
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let text: Vec<&str> = text.lines().map(|line| line.split('#').next().unwrap()).collect();
+        let text = text.join("\n");
+        let memory = block(&text, "MEMORY").ok_or("No MEMORY block")?;
+        let mut areas = vec![];
+        for entry in memory.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            areas.push(parse_area(entry)?);
+        }
+        Ok(Config { areas })
+    }
+
+    /// A memory map with a region per area
+    pub fn memory_map(&self) -> Result<MemoryMap, String> {
+        let mut map = MemoryMap { regions: vec![], rom_writes: Default::default() };
+        for area in self.areas.iter().filter(|area| area.size > 0) {
+            let end = area.start + area.size - 1;
+            if end as usize >= MEMORY_SIZE {
+                return Err(format!("Memory area {} goes past $FFFF", area.name));
+            }
+            map.add(Region {
+                name: area.name.clone(),
+                start: area.start as u16,
+                end: end as u16,
+                region_type: if area.read_only { RegionType::ReadOnly } else { RegionType::ReadWrite },
+                fill: area.fillval,
+                mirror_of: None,
+            })?;
+        }
+        Ok(map)
+    }
+
+    /// Load the output file of the linker into the 64KiB memory `data`. The
+    /// file has the areas written to it one after the other, each one full
+    /// size when filled. An area that is not filled is assumed to end the file.
+    pub fn load(&self, data: &mut [u8], image: &[u8]) -> Result<(), String> {
+        let mut rest = image;
+        for area in self.areas.iter().filter(|area| area.in_output) {
+            if rest.is_empty() {
+                break;
+            }
+            let len = if area.fill { (area.size as usize).min(rest.len()) } else { rest.len() };
+            if len > area.size as usize {
+                return Err(format!("The image is larger than memory area {}", area.name));
+            }
+            place_image(data, &rest[..len], area.start as u16)?;
+            rest = &rest[len..];
+        }
+        if !rest.is_empty() {
+            return Err(format!("{} bytes of the image are not in any memory area", rest.len()));
+        }
+        Ok(())
+    }
+}
+
+/// The contents of the `name { ... }` block
+fn block<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let mut search = text;
+    while let Some(position) = search.find(name) {
+        let after = &search[position + name.len()..];
+        let before_ok = !matches!(search[..position].chars().last(), Some(c) if c.is_alphanumeric() || c == '_');
+        let trimmed = after.trim_start();
+        if before_ok && trimmed.starts_with('{') {
+            let contents = &trimmed[1..];
+            return contents.find('}').map(|end| &contents[..end]);
+        }
+        search = after;
+    }
+    None
+}
+
+/// `NAME: start = $8000, size = $7FFA, type = ro, ...`
+fn parse_area(entry: &str) -> Result<MemoryArea, String> {
+    let mut parts = entry.splitn(2, ':');
+    let name = parts.next().unwrap().trim().to_string();
+    let attributes = parts.next().ok_or_else(|| format!("Invalid memory area {}", entry))?;
+    let mut area = MemoryArea {
+        name,
+        start: 0,
+        size: 0,
+        read_only: false,
+        fill: false,
+        fillval: 0,
+        in_output: true,
+    };
+    let mut has_start = false;
+    for attribute in attributes.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+        let mut parts = attribute.splitn(2, '=');
+        let key = parts.next().unwrap().trim().to_lowercase();
+        let value = parts.next().ok_or_else(|| format!("Invalid attribute {} of {}", attribute, area.name))?.trim();
+        match key.as_str() {
+            "start" => {
+                area.start = parse_value(value, &area.name)?;
+                has_start = true;
+            },
+            "size" => area.size = parse_value(value, &area.name)?,
+            "type" => area.read_only = match value.to_lowercase().as_str() {
+                "ro" => true,
+                "rw" => false,
+                _ => return Err(format!("Unknown type {} of {}", value, area.name)),
+            },
+            "fill" => area.fill = value.eq_ignore_ascii_case("yes"),
+            "fillval" => {
+                let fillval = parse_value(value, &area.name)?;
+                if fillval > 0xff {
+                    return Err(format!("Fill value {} of {} is not a byte", value, area.name));
+                }
+                area.fillval = fillval as u8;
+            },
+            "file" => area.in_output = !value.trim_matches('"').is_empty(),
+            // bank, define and the others do not change the layout
+            _ => {},
+        }
+    }
+    if !has_start {
+        return Err(format!("Memory area {} has no start", area.name));
+    }
+    Ok(area)
+}
+
+/// A number as ld65 writes them: `$8000`, `0x8000` or `32768`. Expressions
+/// and symbols are not supported.
+fn parse_value(value: &str, area: &str) -> Result<u32, String> {
+    let result = if let Some(hex) = value.strip_prefix('$').or_else(|| value.strip_prefix("0x")) {
+        u32::from_str_radix(hex, 16)
+    } else {
+        value.parse::<u32>()
+    };
+    result.map_err(|_| format!("Unsupported value {} in memory area {}", value, area))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../example.cfg");
+
+    #[test]
+    fn parse_example() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        assert_eq!(config.areas.len(), 3);
+        let rom = &config.areas[1];
+        assert_eq!((rom.name.as_str(), rom.start, rom.size), ("ROM", 0x8000, 0x7ffa));
+        assert!(rom.read_only && rom.fill && rom.in_output);
+        assert_eq!(rom.fillval, 0xff);
+        assert!(!config.areas[0].read_only);
+    }
+
+    #[test]
+    fn example_memory_map() {
+        let map = Config::parse(EXAMPLE).unwrap().memory_map().unwrap();
+        let vectors = &map.regions[2];
+        assert_eq!((vectors.start, vectors.end), (0xfffa, 0xffff));
+        assert_eq!(vectors.region_type, RegionType::ReadOnly);
+        assert_eq!(map.regions[0].region_type, RegionType::ReadWrite);
+    }
+
+    #[test]
+    fn areas_are_loaded_in_file_order() {
+        let config = Config::parse("
+            MEMORY {
+                ZP:  start = $0000, size = $0100, type = rw, file = \"\";
+                RAM: start = $0200, size = $0100, file = %O, fill = yes; # padded
+                ROM: start = $E000, size = $2000, type = ro, file = %O;
+            }
+            SEGMENTS { CODE: load = ROM; }
+        ").unwrap();
+        let mut image = vec![0x11; 0x100];
+        image.extend_from_slice(&[0x22, 0x33]);
+        let mut data = vec![0; MEMORY_SIZE];
+        config.load(&mut data, &image).unwrap();
+        assert_eq!((data[0x0200], data[0x02ff], data[0x0300]), (0x11, 0x11, 0x00));
+        assert_eq!((data[0xe000], data[0xe001]), (0x22, 0x33));
+        // Too large for the last area
+        assert!(config.load(&mut data, &vec![0; 0x2101]).is_err());
+    }
+
+    #[test]
+    fn output_file() {
+        let config = Config::parse("
+            MEMORY {
+                ZP:   start = $0000, size = $0100, file = \"\";
+                RAM:  start = $0200, size = $0100;
+                ROM:  start = $E000, size = $2000, file = \"%O\";
+            }
+        ").unwrap();
+        let in_output: Vec<bool> = config.areas.iter().map(|area| area.in_output).collect();
+        assert_eq!(in_output, vec![false, true, true]);
+    }
+
+    #[test]
+    fn unsupported_values() {
+        assert!(Config::parse("MEMORY { RAM: start = __START__, size = $100; }").is_err());
+        assert!(Config::parse("MEMORY { RAM: size = $100; }").is_err());
+        assert!(Config::parse("SEGMENTS { CODE: load = RAM; }").is_err());
+    }
+}
@@ -6,24 +6,55 @@ use cursive::Cursive;
 use cursive::event::Key;
 use cursive::view::*;
 use cursive::views::*;
+use std::cell::Cell;
 use std::sync::mpsc;
 use std::thread;
 
+mod addressing;
+mod banking;
+mod bus;
 mod computer;
+mod cycles;
+mod decimal_test;
+mod device;
+mod image;
+mod instructions;
+mod interrupts;
+mod ld65;
+mod memory_map;
+mod o65;
+mod options;
+mod source;
+mod symbols;
 mod utils;
+mod variant;
 
 use computer::{Processor, Computer, ControllerMessage};
+use banking::BankedBus;
+use bus::Bus;
+use device::Devices;
+use image::{Format, Image};
+use memory_map::{MappedBus, MemoryMap};
+use source::Source;
+use symbols::Symbols;
+use options::Options;
 
 pub struct Ui {
     cursive: Cursive,
     ui_rx: mpsc::Receiver<UiMessage>,
     ui_tx: mpsc::Sender<UiMessage>,
     controller_tx: mpsc::Sender<ControllerMessage>,
+    /// Names shown next to addresses
+    symbols: Symbols,
+    /// Source of the program, followed as it runs
+    source: Option<Source>,
 }
 
 pub enum UiMessage {
     UpdateProcessor(Processor),
-    UpdateData(Vec<u8>),
+    UpdateData(u16, Vec<u8>),
+    UpdateStack(u8, Vec<u8>),
+    UpdateBanks(Vec<String>),
 }
 
 impl Ui {
@@ -36,6 +67,8 @@ impl Ui {
             ui_tx: ui_tx,
             ui_rx: ui_rx,
             controller_tx: controller_tx,
+            symbols: Symbols::default(),
+            source: None,
         };
 
         // Create a view tree with a TextArea for input, and a
@@ -44,6 +77,10 @@ impl Ui {
         let controller_tx_clone1 = ui.controller_tx.clone();
         let controller_tx_clone2 = ui.controller_tx.clone();
         let controller_tx_clone3 = ui.controller_tx.clone();
+        let irq_tx = ui.controller_tx.clone();
+        let nmi_tx = ui.controller_tx.clone();
+        let reset_tx = ui.controller_tx.clone();
+        let irq_asserted = Cell::new(false);
         ui.cursive.add_layer(
             Dialog::around(
                 utils::layout()
@@ -73,6 +110,19 @@ impl Ui {
                 )
                 .unwrap();
             })
+            .button("IRQ", move |_| {
+                // IRQ is level triggered, the button toggles the line
+                irq_asserted.set(!irq_asserted.get());
+                let _ = irq_tx.send(ControllerMessage::SetIrq(irq_asserted.get()));
+            })
+            .button("NMI", move |_| {
+                // NMI is edge triggered, pulse the line
+                let _ = nmi_tx.send(ControllerMessage::SetNmi(true));
+                let _ = nmi_tx.send(ControllerMessage::SetNmi(false));
+            })
+            .button("Reset", move |_| {
+                let _ = reset_tx.send(ControllerMessage::Reset());
+            })
             .button("Quit", |s| {
                 std::process::abort();
                 std::process::exit(0);
@@ -87,6 +137,39 @@ impl Ui {
         ui
     }
 
+    /// Show the regions of the memory map, they do not change while running
+    pub fn show_memory_map(&mut self, lines: &[String]) {
+        let mut output = self.cursive
+            .find_id::<TextView>("memory_map")
+            .unwrap();
+        output.set_content(lines.join("\n"));
+    }
+
+    /// Use `symbols` to name addresses, and list them
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        let lines = symbols.describe();
+        self.symbols = symbols;
+        let mut output = self.cursive
+            .find_id::<TextView>("symbols")
+            .unwrap();
+        if lines.is_empty() {
+            output.set_content("(no symbols)");
+        } else {
+            output.set_content(lines.join("\n"));
+        }
+    }
+
+    /// Show the lines of `source` around PC
+    pub fn set_source(&mut self, source: Option<Source>) {
+        if source.is_none() {
+            let mut output = self.cursive
+                .find_id::<TextView>("source")
+                .unwrap();
+            output.set_content("(no source, see --source)");
+        }
+        self.source = source;
+    }
+
     /// Step the UI by calling into Cursive's step function, then
     /// processing any UI messages.
     pub fn step(&mut self) -> bool {
@@ -103,32 +186,56 @@ impl Ui {
                     let mut output = self.cursive
                         .find_id::<TextView>("flags")
                         .unwrap();
-                    output.set_content(format!("{:b}", processor.flags));
+                    output.set_content(processor.flags_string());
 
                     let mut output = self.cursive
                         .find_id::<TextView>("pc")
                         .unwrap();
-                    output.set_content(format!("{} ({:#x})", processor.pc, processor.pc));
+                    let state = if processor.halted {
+                        " halted"
+                    } else if processor.waiting {
+                        " waiting"
+                    } else {
+                        ""
+                    };
+                    if processor.pbr != 0 {
+                        output.set_content(format!("{} ({:#04x}:{:#06x}){}", processor.pc, processor.pbr, processor.pc, state));
+                    } else if let Some(position) = self.symbols.position(processor.pc) {
+                        output.set_content(format!("{} ({:#x} {}){}", processor.pc, processor.pc, position, state));
+                    } else {
+                        output.set_content(format!("{} ({:#x}){}", processor.pc, processor.pc, state));
+                    }
+                    // The 65C816 shows its whole registers in native mode
+                    let wide = |low: u8, high: u8| if processor.emulation { low as u16 } else { (high as u16) << 8 | low as u16 };
                     let mut output = self.cursive
                         .find_id::<TextView>("acc")
                         .unwrap();
-                    output.set_content(format!("{}", processor.acc));
+                    output.set_content(format!("{}", wide(processor.acc, processor.acc_high)));
                     let mut output = self.cursive
                         .find_id::<TextView>("rx")
                         .unwrap();
-                    output.set_content(format!("{}", processor.rx));
+                    output.set_content(format!("{}", wide(processor.rx, processor.rx_high)));
                     let mut output = self.cursive
                         .find_id::<TextView>("ry")
                         .unwrap();
-                    output.set_content(format!("{}", processor.ry));
+                    output.set_content(format!("{}", wide(processor.ry, processor.ry_high)));
                     let mut output = self.cursive
                         .find_id::<TextView>("sp")
                         .unwrap();
-                    output.set_content(format!("{}", processor.sp));
+                    output.set_content(format!("{}", wide(processor.sp, processor.sp_high)));
                     let mut output = self.cursive
                         .find_id::<TextView>("clock")
                         .unwrap();
                     output.set_content(format!("{}", processor.clock));
+                    let mut output = self.cursive
+                        .find_id::<TextView>("bus")
+                        .unwrap();
+                    // Cycle of the current instruction and the last bus access
+                    let bus = match processor.bus {
+                        Some(bus) => format!("{} {:#06x} {} {:#04x}", processor.cycle, bus.address, if bus.write { "W" } else { "R" }, bus.value),
+                        None => format!("{}", processor.cycle),
+                    };
+                    output.set_content(format!("T{}", bus));
 
                     let mut info = self.cursive
                         .find_id::<TextView>("info")
@@ -153,14 +260,49 @@ impl Ui {
                         .find_id::<TextView>("test")
                         .unwrap();
                     output.set_content(format!("{}", processor.test));
+
+                    // Mid instruction PC is past the opcode, keep the line until the next one
+                    if let (Some(source), 0) = (&self.source, processor.cycle) {
+                        let mut output = self.cursive
+                            .find_id::<TextView>("source")
+                            .unwrap();
+                        output.set_content(utils::source_text(source, processor.pc));
+                    }
                 },
-                UiMessage::UpdateData(data) => {
+                UiMessage::UpdateData(start, data) => {
+                    let lines = utils::memory_lines(start, &data, &self.symbols);
                     let mut output = self.cursive
                         .find_id::<TextView>("memory")
                         .unwrap();
-                    output.set_content(format!("{:x?}", data));
+                    output.set_content(lines.join("\n"));
                     
                 },
+                UiMessage::UpdateStack(sp, stack) => {
+                    let mut output = self.cursive
+                        .find_id::<TextView>("stack")
+                        .unwrap();
+                    // Most recently pushed byte first
+                    let lines: Vec<String> = stack.iter().enumerate().map(|(i, byte)| {
+                        let offset = i + 1;
+                        let address = 0x100 + sp as usize + offset;
+                        format!("SP+{:<3} {:#06x}: {:#04x}", offset, address, byte)
+                    }).collect();
+                    if lines.is_empty() {
+                        output.set_content("(empty)");
+                    } else {
+                        output.set_content(lines.join("\n"));
+                    }
+                },
+                UiMessage::UpdateBanks(banks) => {
+                    let mut output = self.cursive
+                        .find_id::<TextView>("banks")
+                        .unwrap();
+                    if banks.is_empty() {
+                        output.set_content("(no bank switching)");
+                    } else {
+                        output.set_content(banks.join("\n"));
+                    }
+                },
             }
         }
 
@@ -180,23 +322,106 @@ pub struct Controller {
 
 impl Controller {
     /// Create a new controller
-    pub fn new(filename: String) -> Result<Controller, String> {
-        let data = fs::read(filename).expect("could not read file");
+    pub fn new(options: Options) -> Result<Controller, String> {
+        let config = match &options.ld65_cfg {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+                Some(ld65::Config::parse(&text).map_err(|e| format!("{}: {}", path, e))?)
+            },
+            None => None,
+        };
+        let mut map = match (&config, &options.memory_map) {
+            (Some(config), _) => config.memory_map()?,
+            (None, Some(path)) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+                MemoryMap::parse(&text).map_err(|e| format!("{}: {}", path, e))?
+            },
+            (None, None) => MemoryMap::default(),
+        };
+        map.rom_writes = options.rom_writes;
+        let mut data = map.initial_memory();
+        // The ld65 layout and the banked ROM take the image file as it is
+        let raw_image = match &options.filename {
+            Some(filename) if config.is_some() || options.mapper.is_some() => {
+                if Format::from_filename(filename) != Format::Binary {
+                    return Err(format!("{} gives its own addresses, it cannot be placed elsewhere", filename));
+                }
+                Some(fs::read(filename).map_err(|e| format!("Could not read {}: {}", filename, e))?)
+            },
+            _ => None,
+        };
+        let mut images = vec![];
+        if let (Some(filename), None) = (&options.filename, &raw_image) {
+            images.push((filename.clone(), Image::load(filename, options.load_address)?));
+        }
+        for (filename, address) in &options.loads {
+            images.push((filename.clone(), Image::load(filename, *address)?));
+        }
+        // The symbol files come first, o65 objects may import their symbols
+        let mut symbols = Symbols::default();
+        for filename in &options.symbol_files {
+            symbols.load(filename)?;
+        }
+        for (filename, layout) in &options.o65 {
+            images.push((filename.clone(), o65::load(filename, *layout, &mut symbols)?));
+        }
+        let breakpoints = options.breakpoints.iter()
+            .map(|breakpoint| symbols.resolve(breakpoint))
+            .collect::<Result<Vec<u16>, String>>()?;
+        let source = options.source.as_deref().map(Source::load).transpose()?;
+        let computer_symbols = symbols.clone();
+        image::check_overlaps(&images)?;
+        if let (Some(config), Some(raw_image)) = (&config, &raw_image) {
+            config.load(&mut data, raw_image)?;
+        }
+        let mut start_pc = options.start_pc;
+        for (_, image) in &images {
+            image.place(&mut data)?;
+            start_pc = start_pc.or(image.start_pc()?);
+        }
+        let mut map_lines = map.describe();
+        let bus: Box<dyn Bus + Send> = match (&options.mapper, &raw_image) {
+            (Some(name), Some(raw_image)) => {
+                let rom = banking::prg_rom(raw_image)?.to_vec();
+                let mapper = banking::mapper(name, rom.len())?;
+                Box::new(BankedBus::new(MappedBus::new(map, data), rom, mapper))
+            },
+            _ => Box::new(MappedBus::new(map, data)),
+        };
+        // The peripherals of the board are attached here
+        let mut bus = Devices::new(bus);
+        for (name, address) in &options.devices {
+            let (device, size) = device::create(name)?;
+            bus.attach(*address as u32, *address as u32 + size - 1, device)?;
+        }
+        map_lines.extend(bus.describe());
         let (tx, rx) = mpsc::channel::<ControllerMessage>();
         let controller_tx = tx.clone();
         let (computer_tx, computer_rx) = mpsc::channel::<ControllerMessage>();
         let child = thread::spawn(move || {
-            let mut computer = Computer::new(controller_tx, computer_rx, data);
+            let mut computer = Computer::with_bus(controller_tx, computer_rx, bus);
+            computer.set_interrupt_port(options.interrupt_port);
+            computer.set_variant(options.cpu);
+            computer.set_cycle_stepped(options.cycle_stepped);
+            computer.set_symbols(computer_symbols);
+            computer.set_breakpoints(breakpoints);
+            if let Some(pc) = start_pc {
+                computer.set_pc(pc);
+            }
             loop {
                 computer.step();
             }
         });
         
 
+        let mut ui = Ui::new(tx.clone());
+        ui.show_memory_map(&map_lines);
+        ui.set_symbols(symbols);
+        ui.set_source(source);
         Ok(Controller {
             rx: rx,
             ctx: computer_tx.clone(),
-            ui: Ui::new(tx.clone()),
+            ui,
         })
     }
     /// Run the controller
@@ -206,12 +431,21 @@ impl Controller {
         let mut paused: bool = true;
         let mut step: bool = false;
         while self.ui.step() {
-            self.ctx.send(ControllerMessage::GetData());
+            let _ = self.ctx.send(ControllerMessage::GetData());
             while let Some(message) = self.rx.try_iter().next() {
                 // Handle messages arriving from the UI.
                 match message {
                     ControllerMessage::ButtonPressed(btn) => {
-                        self.ctx.send(ControllerMessage::ButtonPressed(btn));
+                        let _ = self.ctx.send(ControllerMessage::ButtonPressed(btn));
+                    },
+                    ControllerMessage::SetIrq(asserted) => {
+                        let _ = self.ctx.send(ControllerMessage::SetIrq(asserted));
+                    },
+                    ControllerMessage::SetNmi(asserted) => {
+                        let _ = self.ctx.send(ControllerMessage::SetNmi(asserted));
+                    },
+                    ControllerMessage::Reset() => {
+                        let _ = self.ctx.send(ControllerMessage::Reset());
                     },
                     ControllerMessage::UpdatedProcessorAvailable(processor) => {
                         self.ui
@@ -220,10 +454,22 @@ impl Controller {
                             .unwrap();
                         //self.computer.step();
                     },
-                    ControllerMessage::UpdatedDataAvailable(data) => {
+                    ControllerMessage::UpdatedDataAvailable(start, data) => {
+                        self.ui
+                            .ui_tx
+                            .send(UiMessage::UpdateData(start, data))
+                            .unwrap();
+                    },
+                    ControllerMessage::UpdatedBanksAvailable(banks) => {
+                        self.ui
+                            .ui_tx
+                            .send(UiMessage::UpdateBanks(banks))
+                            .unwrap();
+                    },
+                    ControllerMessage::UpdatedStackAvailable(sp, stack) => {
                         self.ui
                             .ui_tx
-                            .send(UiMessage::UpdateData(data))
+                            .send(UiMessage::UpdateStack(sp, stack))
                             .unwrap();
                     },
 
@@ -236,13 +482,27 @@ impl Controller {
 
 fn main() {
     // Launch the controller and UI
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("Please enter a filename to run");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, options::USAGE);
+            std::process::exit(1);
+        },
+    };
+
+    if options.decimal_test {
+        match decimal_test::run(options.cpu) {
+            Ok(()) => println!("Decimal mode test passed"),
+            Err(e) => {
+                println!("Decimal mode test failed: {}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
     }
-    let filename = &args[1];
 
-    let controller = Controller::new(filename.to_string());
+    let controller = Controller::new(options);
     match controller {
         Ok(mut controller) => controller.run(),
         Err(e) => println!("Error: {}", e),
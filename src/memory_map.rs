@@ -0,0 +1,358 @@
+use std::fmt;
+
+use crate::bus::{Bus, Ram};
+use crate::computer::MEMORY_SIZE;
+use crate::options::parse_number;
+
+/// What a region of the address space is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionType {
+    /// RAM, read and written
+    ReadWrite,
+    /// ROM, writes do not change it
+    ReadOnly,
+    /// Memory mapped I/O, read and written like RAM until a device is attached
+    Io,
+    /// Nothing answers, reads return the fill value and writes are lost
+    Unmapped,
+}
+
+impl RegionType {
+    fn from_name(name: &str) -> Result<RegionType, String> {
+        match name {
+            "rw" => Ok(RegionType::ReadWrite),
+            "ro" => Ok(RegionType::ReadOnly),
+            "io" => Ok(RegionType::Io),
+            "unmapped" => Ok(RegionType::Unmapped),
+            _ => Err(format!("Unknown region type {}", name)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RegionType::ReadWrite => "rw",
+            RegionType::ReadOnly => "ro",
+            RegionType::Io => "io",
+            RegionType::Unmapped => "unmapped",
+        }
+    }
+}
+
+/// A range of addresses with the same attributes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub start: u16,
+    /// Last address of the region
+    pub end: u16,
+    pub region_type: RegionType,
+    /// What the region holds at power on, or what an unmapped region reads as
+    pub fill: u8,
+    /// Index of the region this one mirrors: its addresses repeat that
+    /// region's contents over and over
+    pub mirror_of: Option<usize>,
+}
+
+impl Region {
+    fn contains(&self, address: u16) -> bool {
+        self.start <= address && address <= self.end
+    }
+
+    fn len(&self) -> u32 {
+        self.end as u32 - self.start as u32 + 1
+    }
+}
+
+/// What happens to a write to ROM or to an unmapped address
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RomWrites {
+    /// It is lost, like on the real hardware
+    #[default]
+    Ignore,
+    /// It is lost and shows up in the debug info
+    Report,
+}
+
+impl RomWrites {
+    pub fn from_name(name: &str) -> Result<RomWrites, String> {
+        match name {
+            "ignore" => Ok(RomWrites::Ignore),
+            "report" => Ok(RomWrites::Report),
+            _ => Err(format!("Unknown ROM write policy {}", name)),
+        }
+    }
+}
+
+/// The regions of the 64KiB address space. Addresses outside all of them
+/// are unmapped and read as $FF.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+    pub rom_writes: RomWrites,
+}
+
+/// What unmapped addresses outside any region read as
+const OPEN_BUS: u8 = 0xff;
+
+impl Default for MemoryMap {
+    /// RAM everywhere
+    fn default() -> MemoryMap {
+        MemoryMap {
+            regions: vec![Region {
+                name: "RAM".to_string(),
+                start: 0x0000,
+                end: 0xffff,
+                region_type: RegionType::ReadWrite,
+                fill: 0,
+                mirror_of: None,
+            }],
+            rom_writes: RomWrites::default(),
+        }
+    }
+}
+
+impl MemoryMap {
+    /// Parse a memory map description, a region per line:
+    ///
+    /// ```text
+    /// # name     range        type  options
+    /// RAM        $0000-$07FF  rw    fill=$00
+    /// RAM_MIRROR $0800-$1FFF  mirror=RAM
+    /// ROM        $8000-$FFFF  ro    fill=$FF
+    /// ```
+    ///
+    /// The type is `rw`, `ro`, `io` or `unmapped`. A mirror takes the type
+    /// of the region it mirrors, which must come before it. A mirror of a
+    /// mirror repeats the region the first one mirrors.
+    pub fn parse(text: &str) -> Result<MemoryMap, String> {
+        let mut map = MemoryMap { regions: vec![], rom_writes: RomWrites::default() };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let region = map.parse_region(line).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+            map.add(region).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+        Ok(map)
+    }
+
+    fn parse_region(&self, line: &str) -> Result<Region, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap().to_string();
+        let range = words.next().ok_or_else(|| format!("Missing address range for {}", name))?;
+        let (start, end) = parse_range(range)?;
+        let mut region = Region { name, start, end, region_type: RegionType::ReadWrite, fill: 0, mirror_of: None };
+        for word in words {
+            if let Some(fill) = word.strip_prefix("fill=") {
+                let fill = parse_number(fill)?;
+                if fill > 0xff {
+                    return Err(format!("Fill value {} is not a byte", word));
+                }
+                region.fill = fill as u8;
+            } else if let Some(target) = word.strip_prefix("mirror=") {
+                let index = self.regions.iter().position(|r| r.name == target)
+                    .ok_or_else(|| format!("No region {} to mirror", target))?;
+                region.mirror_of = Some(self.regions[index].mirror_of.unwrap_or(index));
+                region.region_type = self.regions[index].region_type;
+            } else {
+                region.region_type = RegionType::from_name(word)?;
+            }
+        }
+        Ok(region)
+    }
+
+    /// Add a region, it must not overlap the others
+    pub fn add(&mut self, region: Region) -> Result<(), String> {
+        if region.end < region.start {
+            return Err(format!("Region {} ends before it starts", region.name));
+        }
+        if let Some(other) = self.regions.iter().find(|r| r.start <= region.end && region.start <= r.end) {
+            return Err(format!("Region {} overlaps {}", region.name, other.name));
+        }
+        self.regions.push(region);
+        Ok(())
+    }
+
+    /// The region `address` is in and where it ends up once mirrors are followed
+    pub fn translate(&self, address: u16) -> Option<(&Region, u16)> {
+        let region = self.regions.iter().find(|r| r.contains(address))?;
+        match region.mirror_of {
+            Some(index) => {
+                let target = &self.regions[index];
+                let offset = (address - region.start) as u32 % target.len();
+                Some((target, target.start + offset as u16))
+            },
+            None => Some((region, address)),
+        }
+    }
+
+    /// The 64KiB memory at power on, with each region holding its fill value
+    pub fn initial_memory(&self) -> Vec<u8> {
+        let mut data = vec![0; MEMORY_SIZE];
+        for region in self.regions.iter().filter(|r| r.mirror_of.is_none()) {
+            data[region.start as usize..=region.end as usize].iter_mut().for_each(|byte| *byte = region.fill);
+        }
+        data
+    }
+
+    /// A line per region, for the UI
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.regions.iter().map(|region| region.to_string()).collect();
+        if self.rom_writes == RomWrites::Report {
+            lines.push("Writes to ROM are reported".to_string());
+        }
+        lines
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04X}-${:04X} {:<12} {}", self.start, self.end, self.name, self.region_type.name())?;
+        if let Some(index) = self.mirror_of {
+            write!(f, " mirror #{}", index)?;
+        } else if self.fill != 0 {
+            write!(f, " fill=${:02X}", self.fill)?;
+        }
+        Ok(())
+    }
+}
+
+/// `$start-$end`, both inclusive
+fn parse_range(range: &str) -> Result<(u16, u16), String> {
+    let mut bounds = range.splitn(2, '-');
+    let start = parse_number(bounds.next().unwrap())?;
+    let end = bounds.next().ok_or_else(|| format!("Invalid range {}, expected start-end", range))?;
+    Ok((start, parse_number(end)?))
+}
+
+/// RAM seen through a memory map
+#[derive(Debug)]
+pub struct MappedBus {
+    ram: Ram,
+    map: MemoryMap,
+    /// A write the map refused, waiting to be reported
+    fault: Option<String>,
+}
+
+impl MappedBus {
+    /// `data` is the 64KiB memory, usually from `MemoryMap::initial_memory`
+    /// with the image loaded over it
+    pub fn new(map: MemoryMap, data: Vec<u8>) -> MappedBus {
+        MappedBus { ram: Ram::new(data), map, fault: None }
+    }
+}
+
+impl Bus for MappedBus {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        // The banks of the 65C816 above the first 64KiB are plain RAM
+        if address > 0xffff {
+            self.ram.write(address, value);
+            return;
+        }
+        match self.map.translate(address as u16) {
+            Some((region, target)) if region.region_type == RegionType::ReadWrite || region.region_type == RegionType::Io => {
+                self.ram.write(target as u32, value);
+            },
+            region => {
+                if self.map.rom_writes == RomWrites::Report {
+                    let name = region.map(|(r, _)| r.name.as_str()).unwrap_or("unmapped memory");
+                    self.fault = Some(format!("Write of {:#04x} to {} at {:#06x} ignored", value, name, address));
+                }
+            },
+        }
+    }
+
+    fn peek(&self, address: u32) -> u8 {
+        if address > 0xffff {
+            return self.ram.peek(address);
+        }
+        match self.map.translate(address as u16) {
+            Some((region, _)) if region.region_type == RegionType::Unmapped => region.fill,
+            Some((_, target)) => self.ram.peek(target as u32),
+            None => OPEN_BUS,
+        }
+    }
+
+    fn take_fault(&mut self) -> Option<String> {
+        self.fault.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NES: &str = "
+        # NES-like map
+        RAM        $0000-$07FF  rw
+        RAM_MIRROR $0800-$1FFF  mirror=RAM
+        IO         $2000-$2007  io
+        NOTHING    $4020-$5FFF  unmapped fill=$EA
+        ROM        $8000-$FFFF  ro fill=$FF
+    ";
+
+    #[test]
+    fn parse_regions() {
+        let map = MemoryMap::parse(NES).unwrap();
+        assert_eq!(map.regions.len(), 5);
+        assert_eq!(map.regions[1].mirror_of, Some(0));
+        assert_eq!(map.regions[1].region_type, RegionType::ReadWrite);
+        assert_eq!((map.regions[4].start, map.regions[4].end, map.regions[4].fill), (0x8000, 0xffff, 0xff));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(MemoryMap::parse("RAM $0000-$07FF rx").is_err());
+        assert!(MemoryMap::parse("RAM $0800-$07FF rw").is_err());
+        assert!(MemoryMap::parse("A $0000-$07FF rw\nB $0700-$0FFF ro").is_err());
+        assert!(MemoryMap::parse("MIRROR $0800-$0FFF mirror=RAM").is_err());
+    }
+
+    #[test]
+    fn mirrors_repeat_their_region() {
+        let mut bus = MappedBus::new(MemoryMap::parse(NES).unwrap(), vec![0; MEMORY_SIZE]);
+        bus.write(0x1801, 0x42);
+        assert_eq!(bus.peek(0x0001), 0x42);
+        assert_eq!(bus.peek(0x0801), 0x42);
+    }
+
+    #[test]
+    fn mirrors_of_mirrors() {
+        let map = MemoryMap::parse("A $0000-$00FF rw\nB $0100-$01FF mirror=A\nC $0200-$02FF mirror=B").unwrap();
+        assert_eq!(map.regions[2].mirror_of, Some(0));
+        let mut bus = MappedBus::new(map, vec![0; MEMORY_SIZE]);
+        bus.write(0x0210, 0x42);
+        assert_eq!((bus.peek(0x0010), bus.peek(0x0110)), (0x42, 0x42));
+        bus.write(0x0120, 0x24);
+        assert_eq!(bus.peek(0x0220), 0x24);
+    }
+
+    #[test]
+    fn rom_writes_are_ignored() {
+        let map = MemoryMap::parse(NES).unwrap();
+        let mut bus = MappedBus::new(map.clone(), map.initial_memory());
+        bus.write(0x8000, 0x42);
+        assert_eq!(bus.peek(0x8000), 0xff);
+        assert_eq!(bus.take_fault(), None);
+
+        let mut bus = MappedBus::new(MemoryMap { rom_writes: RomWrites::Report, ..map.clone() }, map.initial_memory());
+        bus.write(0x8000, 0x42);
+        assert_eq!(bus.peek(0x8000), 0xff);
+        assert!(bus.take_fault().is_some());
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn unmapped_reads() {
+        let mut bus = MappedBus::new(MemoryMap::parse(NES).unwrap(), vec![0; MEMORY_SIZE]);
+        bus.write(0x5000, 0x42);
+        assert_eq!(bus.peek(0x5000), 0xea);
+        // Outside all the regions
+        assert_eq!(bus.peek(0x3000), 0xff);
+    }
+}
@@ -0,0 +1,344 @@
+//! The o65 relocatable object format of xa and ld65. A file holds the text
+//! and data segments as assembled for some base addresses, with tables
+//! saying which bytes hold addresses, so the segments can be moved
+//! anywhere. Only the 16-bit flavour of the format is supported.
+
+use std::fs;
+
+use crate::image::{Image, Segment};
+use crate::options::parse_number;
+use crate::symbols::Symbols;
+
+const MAGIC: [u8; 6] = [0x01, 0x00, b'o', b'6', b'5', 0x00];
+
+/// Mode bits of the header
+const MODE_PAGED: u16 = 0x4000;
+const MODE_32BIT: u16 = 0x2000;
+
+/// Relocation types, the high bits of the type byte
+const RELOC_WORD: u8 = 0x80;
+const RELOC_HIGH: u8 = 0x40;
+const RELOC_LOW: u8 = 0x20;
+
+/// Segment number of the references to undefined symbols, then come the
+/// absolute, text, data, bss and zero page segments
+const SEGMENT_UNDEFINED: u8 = 0;
+
+/// Where the segments go. Segments not given move along with the text
+/// segment, the zero page segment stays where it was assembled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Layout {
+    pub text: Option<u16>,
+    pub data: Option<u16>,
+    pub bss: Option<u16>,
+    pub zero_page: Option<u16>,
+}
+
+impl Layout {
+    /// `text=$2000,data=$3000,bss=$4000,zp=$80`, any of them can be left out.
+    /// A lone address is where the text segment goes.
+    pub fn parse(text: &str) -> Result<Layout, String> {
+        let mut layout = Layout::default();
+        for part in text.split(',') {
+            let mut parts = part.splitn(2, '=');
+            let (segment, address) = match (parts.next().unwrap(), parts.next()) {
+                (address, None) => ("text", address),
+                (segment, Some(address)) => (segment, address),
+            };
+            let address = Some(parse_number(address)?);
+            match segment {
+                "text" => layout.text = address,
+                "data" => layout.data = address,
+                "bss" => layout.bss = address,
+                "zp" => layout.zero_page = address,
+                _ => return Err(format!("Unknown o65 segment {}", segment)),
+            }
+        }
+        Ok(layout)
+    }
+}
+
+/// A relocation entry: a byte or word of a segment holding an address
+#[derive(Clone, Debug, PartialEq)]
+struct Relocation {
+    offset: usize,
+    kind: u8,
+    segment: u8,
+    /// Undefined reference the address is relative to
+    symbol: Option<usize>,
+    /// Low byte of the address a HIGH relocation takes the high byte of
+    low: u8,
+}
+
+impl Relocation {
+    /// Move the address at the offset of the entry in `segment` by `delta`
+    fn apply(&self, segment: &mut [u8], delta: u16) -> Result<(), String> {
+        let offset = self.offset;
+        let len = if self.kind == RELOC_WORD { 2 } else { 1 };
+        if offset + len > segment.len() {
+            return Err(format!("Relocation at offset {:#x} is outside its segment", offset));
+        }
+        match self.kind {
+            RELOC_WORD => {
+                let value = (segment[offset] as u16 | (segment[offset + 1] as u16) << 8).wrapping_add(delta);
+                segment[offset] = value as u8;
+                segment[offset + 1] = (value >> 8) as u8;
+            },
+            RELOC_HIGH => {
+                let value = ((segment[offset] as u16) << 8 | self.low as u16).wrapping_add(delta);
+                segment[offset] = (value >> 8) as u8;
+            },
+            _ => segment[offset] = segment[offset].wrapping_add(delta as u8),
+        }
+        Ok(())
+    }
+}
+
+/// A parsed o65 file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    text_base: u16,
+    data_base: u16,
+    bss_base: u16,
+    bss_len: u16,
+    zero_page_base: u16,
+    text: Vec<u8>,
+    data: Vec<u8>,
+    undefined: Vec<String>,
+    text_relocations: Vec<Relocation>,
+    data_relocations: Vec<Relocation>,
+    /// Name, segment and value as assembled
+    exports: Vec<(String, u8, u16)>,
+}
+
+/// Read, relocate and place the o65 file `filename`. Its references to
+/// undefined symbols are resolved with `symbols`, and its exported symbols
+/// are added to them.
+pub fn load(filename: &str, layout: Layout, symbols: &mut Symbols) -> Result<Image, String> {
+    let contents = fs::read(filename).map_err(|e| format!("Could not read {}: {}", filename, e))?;
+    let object = Object::parse(&contents).map_err(|e| format!("{}: {}", filename, e))?;
+    object.relocate(layout, symbols).map_err(|e| format!("{}: {}", filename, e))
+}
+
+/// Reads the fields of the file in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.bytes.get(self.position..self.position + len).ok_or("The file is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    /// A name, ended by a 0 byte
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.bytes[self.position..].iter().position(|&b| b == 0).ok_or("The file is truncated")?;
+        let name = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.position += 1;
+        Ok(name)
+    }
+
+    /// A relocation table, its offsets start 1 byte before the segment
+    fn relocations(&mut self, paged: bool) -> Result<Vec<Relocation>, String> {
+        let mut relocations = vec![];
+        let mut offset: isize = -1;
+        loop {
+            match self.byte()? {
+                0 => return Ok(relocations),
+                255 => offset += 254,
+                step => {
+                    offset += step as isize;
+                    let type_byte = self.byte()?;
+                    let (kind, segment) = (type_byte & 0xe0, type_byte & 0x0f);
+                    let symbol = if segment == SEGMENT_UNDEFINED { Some(self.word()? as usize) } else { None };
+                    let low = match kind {
+                        RELOC_HIGH if !paged => self.byte()?,
+                        RELOC_WORD | RELOC_HIGH | RELOC_LOW => 0,
+                        _ => return Err(format!("Unsupported relocation type {:#04x}", kind)),
+                    };
+                    relocations.push(Relocation { offset: offset as usize, kind, segment, symbol, low });
+                },
+            }
+        }
+    }
+}
+
+impl Object {
+    pub fn parse(bytes: &[u8]) -> Result<Object, String> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err("Not an o65 file".to_string());
+        }
+        let mode = reader.word()?;
+        if mode & MODE_32BIT != 0 {
+            return Err("32-bit o65 files are not supported".to_string());
+        }
+        let text_base = reader.word()?;
+        let text_len = reader.word()?;
+        let data_base = reader.word()?;
+        let data_len = reader.word()?;
+        let bss_base = reader.word()?;
+        let bss_len = reader.word()?;
+        let zero_page_base = reader.word()?;
+        let _zero_page_len = reader.word()?;
+        let _stack_len = reader.word()?;
+        // Header options, each one starts with its length
+        loop {
+            match reader.byte()? {
+                0 => break,
+                len => { reader.take(len as usize - 1)?; },
+            }
+        }
+        let text = reader.take(text_len as usize)?.to_vec();
+        let data = reader.take(data_len as usize)?.to_vec();
+        let undefined = (0..reader.word()?).map(|_| reader.name()).collect::<Result<Vec<_>, _>>()?;
+        let paged = mode & MODE_PAGED != 0;
+        let text_relocations = reader.relocations(paged)?;
+        let data_relocations = reader.relocations(paged)?;
+        let mut exports = vec![];
+        for _ in 0..reader.word()? {
+            let name = reader.name()?;
+            let segment = reader.byte()?;
+            let value = reader.word()?;
+            exports.push((name, segment, value));
+        }
+        Ok(Object {
+            text_base, data_base, bss_base, bss_len, zero_page_base,
+            text, data, undefined, text_relocations, data_relocations, exports,
+        })
+    }
+
+    /// Move the segments where `layout` says, with the references to
+    /// undefined symbols taken from `symbols`. The exported symbols are
+    /// added to `symbols`.
+    pub fn relocate(&self, layout: Layout, symbols: &mut Symbols) -> Result<Image, String> {
+        let text_base = layout.text.unwrap_or(self.text_base);
+        let shift = text_base.wrapping_sub(self.text_base);
+        let data_base = layout.data.unwrap_or_else(|| self.data_base.wrapping_add(shift));
+        let bss_base = layout.bss.unwrap_or_else(|| self.bss_base.wrapping_add(shift));
+        let zero_page_base = layout.zero_page.unwrap_or(self.zero_page_base);
+        if text_base as usize + self.text.len() > 0x10000 || data_base as usize + self.data.len() > 0x10000
+            || bss_base as usize + self.bss_len as usize > 0x10000 {
+            return Err("The segments do not fit in the address space".to_string());
+        }
+
+        // How far each segment moves, by segment number
+        let deltas = [
+            0,
+            0,
+            shift,
+            data_base.wrapping_sub(self.data_base),
+            bss_base.wrapping_sub(self.bss_base),
+            zero_page_base.wrapping_sub(self.zero_page_base),
+        ];
+        let delta = |segment: u8, symbol: Option<usize>| -> Result<u16, String> {
+            if segment != SEGMENT_UNDEFINED {
+                return deltas.get(segment as usize).copied().ok_or_else(|| format!("Invalid segment {}", segment));
+            }
+            let name = symbol.and_then(|index| self.undefined.get(index)).ok_or("Invalid undefined reference")?;
+            symbols.address(name).ok_or_else(|| format!("Undefined symbol {}", name))
+        };
+
+        let mut text = self.text.clone();
+        let mut data = self.data.clone();
+        for relocation in &self.text_relocations {
+            relocation.apply(&mut text, delta(relocation.segment, relocation.symbol)?)?;
+        }
+        for relocation in &self.data_relocations {
+            relocation.apply(&mut data, delta(relocation.segment, relocation.symbol)?)?;
+        }
+        let exports = self.exports.iter()
+            .map(|(name, segment, value)| Ok((name, value.wrapping_add(delta(*segment, None)?))))
+            .collect::<Result<Vec<_>, String>>()?;
+        for (name, address) in exports {
+            symbols.add(name, address);
+        }
+        Ok(Image {
+            segments: vec![
+                Segment { address: text_base as u32, data: text },
+                Segment { address: data_base as u32, data },
+            ],
+            start: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text at $1000 and data at $2000:
+    ///
+    /// ```text
+    /// start: LDA value      ; AD 00 20
+    ///        LDA #>start    ; A9 10
+    ///        JMP start      ; 4C 00 10
+    ///        JSR putc       ; 20 00 00
+    /// value: .byte $42, .word start
+    /// ```
+    fn module() -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        let header: [u16; 10] = [0, 0x1000, 11, 0x2000, 3, 0x3000, 0, 0x0080, 0, 0];
+        header.iter().for_each(|word| file.extend_from_slice(&word.to_le_bytes()));
+        // A filename option
+        file.extend_from_slice(&[4, 0, b'x', 0, 0]);
+        file.extend_from_slice(&[0xad, 0x00, 0x20, 0xa9, 0x10, 0x4c, 0x00, 0x10, 0x20, 0x00, 0x00]);
+        file.extend_from_slice(&[0x42, 0x00, 0x10]);
+        // Undefined references
+        file.extend_from_slice(&[1, 0]);
+        file.extend_from_slice(b"putc\0");
+        // Text relocations, the HIGH one has the low byte of the address
+        file.extend_from_slice(&[2, 0x83, 3, 0x42, 0x00, 2, 0x82, 3, 0x80, 0, 0, 0]);
+        // Data relocations
+        file.extend_from_slice(&[2, 0x82, 0]);
+        // Exports
+        file.extend_from_slice(&[2, 0]);
+        file.extend_from_slice(b"start\0\x02\x00\x10");
+        file.extend_from_slice(b"value\0\x03\x00\x20");
+        file
+    }
+
+    #[test]
+    fn relocate_segments() {
+        let object = Object::parse(&module()).unwrap();
+        let mut symbols = Symbols::default();
+        symbols.add("putc", 0xffd2);
+        let image = object.relocate(Layout::parse("$3000").unwrap(), &mut symbols).unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { address: 0x3000, data: vec![0xad, 0x00, 0x40, 0xa9, 0x30, 0x4c, 0x00, 0x30, 0x20, 0xd2, 0xff] },
+            Segment { address: 0x4000, data: vec![0x42, 0x00, 0x30] },
+        ]);
+        assert_eq!((symbols.address("start"), symbols.address("value")), (Some(0x3000), Some(0x4000)));
+    }
+
+    #[test]
+    fn data_placed_apart() {
+        let object = Object::parse(&module()).unwrap();
+        let mut symbols = Symbols::default();
+        symbols.add("putc", 0xffd2);
+        let image = object.relocate(Layout::parse("text=$0800,data=$c000").unwrap(), &mut symbols).unwrap();
+        assert_eq!(&image.segments[0].data[..3], &[0xad, 0x00, 0xc0]);
+        assert_eq!(image.segments[1].address, 0xc000);
+    }
+
+    #[test]
+    fn undefined_symbols() {
+        let object = Object::parse(&module()).unwrap();
+        let error = object.relocate(Layout::default(), &mut Symbols::default()).unwrap_err();
+        assert_eq!(error, "Undefined symbol putc");
+        assert!(Object::parse(&module()[..40]).is_err());
+        assert!(Layout::parse("code=$1000").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::memory_map::RomWrites;
+use crate::o65::Layout;
+use crate::variant::Variant;
+
+pub const USAGE: &str = "Usage: rust_test [options] [<image>]
+
+The image is a raw binary, a Commodore .prg loaded at the address in its
+header, or Intel HEX (.hex, .ihx) or Motorola S-records (.s19, .s28, .s37,
+.srec) loaded at the addresses of their records.
+
+Options:
+    --decimal-test            run Bruce Clark's decimal mode test and exit
+    --start-pc <addr>         start at <addr> instead of the reset vector
+    --load-address <addr>     load an image smaller than 64KiB at <addr> (default 0)
+    --load <file>[@<addr>]    load another image, at <addr> if given; can be repeated
+    --o65 <file>[@<layout>]   load an o65 relocatable object, with its segments at
+                              text=<addr>,data=<addr>,bss=<addr>,zp=<addr>
+    --memory-map <file>       RAM, ROM, I/O and unmapped regions of the address space
+    --rom-writes <policy>     ignore (default) or report writes to ROM
+    --ld65-cfg <file>         memory map and image layout from an ld65 linker config
+    --mapper <scheme>         the image is a banked ROM at $8000: nrom, uxrom, or
+                              window:<addr> for a 16KiB window switched by <addr>
+    --symbols <file>          symbols from an ld65 map (-m), VICE labels (-Ln) or a
+                              ca65 debug info file (--dbgfile); can be repeated
+    --source <file>           ca65 listing (-l) or debug info file (--dbgfile) to show
+                              the source around PC
+    --break <addr>            pause when PC gets to <addr>, a number or a symbol;
+                              can be repeated
+    --device <name>@<addr>    attach a device at <addr>: timer, a one-shot interval
+                              timer raising IRQ; can be repeated
+    --interrupt-port <addr>   writes to <addr> drive IRQ (bit 0) and NMI (bit 1)
+    --cycle-stepped           step a clock cycle at a time instead of an instruction
+    --cpu <name>              processor to emulate: 6502 (default), 65c02, 2a03, 6510
+                              or 65816";
+
+/// Command line options
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// The image to run
+    pub filename: Option<String>,
+    /// Run the decimal mode test without the UI instead of an image
+    pub decimal_test: bool,
+    /// Address of the interrupt feedback register
+    pub interrupt_port: Option<u16>,
+    /// Where the image goes in the 64KiB address space
+    pub load_address: Option<u16>,
+    /// More images and where they go
+    pub loads: Vec<(String, Option<u16>)>,
+    /// o65 objects and where their segments go
+    pub o65: Vec<(String, Layout)>,
+    /// File describing the memory map, RAM everywhere without one
+    pub memory_map: Option<String>,
+    /// ld65 configuration giving the memory map and where the image goes
+    pub ld65_cfg: Option<String>,
+    /// Bank switching scheme of the ROM image
+    pub mapper: Option<String>,
+    /// Files to read symbols from
+    pub symbol_files: Vec<String>,
+    /// Listing or debug info file giving the source of the program
+    pub source: Option<String>,
+    /// Breakpoints, as addresses or symbols
+    pub breakpoints: Vec<String>,
+    /// Devices to attach and their addresses
+    pub devices: Vec<(String, u16)>,
+    /// What happens to writes to ROM
+    pub rom_writes: RomWrites,
+    /// Start address overriding the reset vector
+    pub start_pc: Option<u16>,
+    /// Use the core that runs a clock cycle per step
+    pub cycle_stepped: bool,
+    /// The processor to emulate
+    pub cpu: Variant,
+}
+
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--decimal-test" => options.decimal_test = true,
+            "--cycle-stepped" => options.cycle_stepped = true,
+            "--start-pc" => {
+                options.start_pc = Some(parse_number(value(&mut args, arg)?)?);
+            },
+            "--load-address" => {
+                options.load_address = Some(parse_number(value(&mut args, arg)?)?);
+            },
+            "--load" => {
+                options.loads.push(parse_load(value(&mut args, arg)?)?);
+            },
+            "--o65" => {
+                let value = value(&mut args, arg)?;
+                let mut parts = value.splitn(2, '@');
+                let filename = parts.next().unwrap().to_string();
+                let layout = parts.next().map(Layout::parse).transpose()?.unwrap_or_default();
+                options.o65.push((filename, layout));
+            },
+            "--symbols" => {
+                options.symbol_files.push(value(&mut args, arg)?.to_string());
+            },
+            "--source" => {
+                options.source = Some(value(&mut args, arg)?.to_string());
+            },
+            "--break" => {
+                options.breakpoints.push(value(&mut args, arg)?.to_string());
+            },
+            "--memory-map" => {
+                options.memory_map = Some(value(&mut args, arg)?.to_string());
+            },
+            "--ld65-cfg" => {
+                options.ld65_cfg = Some(value(&mut args, arg)?.to_string());
+            },
+            "--mapper" => {
+                options.mapper = Some(value(&mut args, arg)?.to_string());
+            },
+            "--rom-writes" => {
+                options.rom_writes = RomWrites::from_name(value(&mut args, arg)?)?;
+            },
+            "--cpu" => {
+                options.cpu = Variant::from_name(value(&mut args, arg)?)?;
+            },
+            "--device" => {
+                let value = value(&mut args, arg)?;
+                let mut parts = value.splitn(2, '@');
+                let name = parts.next().unwrap().to_string();
+                let address = parts.next().ok_or_else(|| format!("--device {} needs an address, like timer@$d000", value))?;
+                options.devices.push((name, parse_number(address)?));
+            },
+            "--interrupt-port" => {
+                options.interrupt_port = Some(parse_number(value(&mut args, arg)?)?);
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => {
+                if options.filename.is_some() {
+                    return Err(format!("Unexpected argument {}", arg));
+                }
+                options.filename = Some(arg.to_string());
+            },
+        }
+    }
+
+    if options.cycle_stepped && options.cpu == Variant::W65c816 {
+        return Err("--cycle-stepped is not available for the 65C816".to_string());
+    }
+
+    if options.ld65_cfg.is_some() && (options.memory_map.is_some() || options.load_address.is_some()) {
+        return Err("--ld65-cfg already gives the memory map and the load address".to_string());
+    }
+
+    if options.mapper.is_some() && (options.ld65_cfg.is_some() || options.load_address.is_some()) {
+        return Err("--mapper puts the image at $8000, it cannot be placed elsewhere".to_string());
+    }
+
+    if options.filename.is_none() && (options.ld65_cfg.is_some() || options.mapper.is_some()) {
+        return Err("--ld65-cfg and --mapper need an image filename".to_string());
+    }
+
+    if options.filename.is_none() && options.loads.is_empty() && options.o65.is_empty() && !options.decimal_test {
+        return Err("Please enter a filename to run".to_string());
+    }
+
+    Ok(options)
+}
+
+/// The value following an option
+fn value<'a>(args: &mut std::slice::Iter<'a, String>, option: &str) -> Result<&'a str, String> {
+    args.next()
+        .map(|v| v.as_str())
+        .ok_or_else(|| format!("Missing value for {}", option))
+}
+
+/// `file@addr`, or just `file`
+fn parse_load(load: &str) -> Result<(String, Option<u16>), String> {
+    let mut parts = load.rsplitn(2, '@');
+    let last = parts.next().unwrap();
+    match parts.next() {
+        Some(filename) => Ok((filename.to_string(), Some(parse_number(last)?))),
+        None => Ok((load.to_string(), None)),
+    }
+}
+
+/// Parse an address or other number given as `0x1234`, `$1234` or decimal
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let result = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.parse::<u16>()
+    };
+    result.map_err(|_| format!("Invalid number {}", s))
+}
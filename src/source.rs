@@ -0,0 +1,208 @@
+//! Where the instruction at each address comes from in the assembly source,
+//! from a ca65 listing (`-l`) or the line info of a debug info file
+//! (`--dbgfile`).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::symbols::debug_attributes;
+
+/// Column of the source text in a ca65 listing, after the address, the
+/// include depth and the bytes
+const LISTING_SOURCE: usize = 24;
+
+/// A source file
+#[derive(Clone, Debug, Default, PartialEq)]
+struct File {
+    name: String,
+    lines: Vec<String>,
+}
+
+/// Source files and the line each address comes from
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    files: Vec<File>,
+    /// File and line index of each address
+    addresses: HashMap<u16, (usize, usize)>,
+}
+
+impl Source {
+    /// Read a listing or a debug info file
+    pub fn load(filename: &str) -> Result<Source, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("Could not read {}: {}", filename, e))?;
+        let source = if text.starts_with("version\t") {
+            let directory = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+            Source::from_debug_info(&text, directory)
+        } else {
+            Source::from_listing(filename, &text)
+        };
+        source.map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    /// A ca65 listing. Each line has the address, the include depth and up
+    /// to 4 of the bytes it assembles to before the source text. Addresses
+    /// followed by `r` are relative to a segment the listing does not place,
+    /// only the others can be mapped.
+    pub fn from_listing(name: &str, text: &str) -> Result<Source, String> {
+        let mut source = Source::default();
+        let mut lines = vec![];
+        for line in text.lines() {
+            let index = lines.len();
+            lines.push(line.to_string());
+            let address = match line.get(..7) {
+                Some(address) if address.ends_with(' ') => address.trim_end(),
+                _ => continue,
+            };
+            let address = match u32::from_str_radix(address, 16) {
+                Ok(address) => address as u16,
+                Err(_) => continue,
+            };
+            // Only the lines with bytes hold instructions or data
+            let has_bytes = !line.get(11..LISTING_SOURCE).unwrap_or("").trim().is_empty();
+            if has_bytes {
+                source.addresses.entry(address).or_insert((0, index));
+            }
+        }
+        if source.addresses.is_empty() {
+            return Err("No absolute addresses in the listing".to_string());
+        }
+        source.files.push(File { name: name.to_string(), lines });
+        Ok(source)
+    }
+
+    /// The line info of a ca65 debug info file. The source files it names
+    /// are read from `directory` if they are not found as they are.
+    pub fn from_debug_info(text: &str, directory: &Path) -> Result<Source, String> {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut lines = vec![];
+        for line in text.lines() {
+            let mut parts = line.splitn(2, '\t');
+            let (kind, attributes) = (parts.next().unwrap(), debug_attributes(parts.next().unwrap_or("")));
+            let number = |key: &str| -> Result<u32, String> {
+                let value = attributes.get(key).ok_or_else(|| format!("No {} in {}", key, line))?;
+                let result = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                result.map_err(|_| format!("Invalid {} in {}", key, line))
+            };
+            match kind {
+                "file" => {
+                    let name = attributes.get("name").ok_or_else(|| format!("No name in {}", line))?;
+                    files.insert(number("id")?, name.trim_matches('"').to_string());
+                },
+                "seg" => { segments.insert(number("id")?, number("start")?); },
+                "span" => { spans.insert(number("id")?, (number("seg")?, number("start")?, number("size")?)); },
+                "line" if attributes.contains_key("span") => {
+                    // Macro expansions and C lines come after the assembly lines
+                    let from_assembly = attributes.get("type").copied().unwrap_or("0") == "0";
+                    let span_ids = attributes["span"].split('+')
+                        .map(|id| id.parse::<u32>().map_err(|_| format!("Invalid span in {}", line)))
+                        .collect::<Result<Vec<u32>, String>>()?;
+                    lines.push((!from_assembly, number("file")?, number("line")?, span_ids));
+                },
+                _ => {},
+            }
+        }
+        lines.sort_by_key(|&(later, ..)| later);
+
+        let mut source = Source::default();
+        let mut file_indexes = HashMap::new();
+        for (_, file, line, span_ids) in lines {
+            if line == 0 {
+                return Err(format!("Line 0 of file {}, lines start at 1", file));
+            }
+            let index = match file_indexes.get(&file) {
+                Some(&index) => index,
+                None => {
+                    let name = files.get(&file).ok_or_else(|| format!("No file {}", file))?;
+                    source.files.push(File { name: name.clone(), lines: read_source(name, directory)? });
+                    file_indexes.insert(file, source.files.len() - 1);
+                    source.files.len() - 1
+                },
+            };
+            for span in span_ids {
+                let &(segment, start, size) = spans.get(&span).ok_or_else(|| format!("No span {}", span))?;
+                let base = segments.get(&segment).ok_or_else(|| format!("No segment {}", segment))?;
+                for address in base + start..base + start + size {
+                    source.addresses.entry(address as u16).or_insert((index, line as usize - 1));
+                }
+            }
+        }
+        Ok(source)
+    }
+
+    /// The name of the file and the lines around the one `address` comes
+    /// from, with the index of that line among them
+    pub fn around(&self, address: u16, context: usize) -> Option<(&str, &[String], usize)> {
+        let &(file, line) = self.addresses.get(&address)?;
+        let file = &self.files[file];
+        // The source may have been edited since the debug info was written
+        if line >= file.lines.len() {
+            return None;
+        }
+        let first = line.saturating_sub(context);
+        let last = (line + context + 1).min(file.lines.len());
+        Some((&file.name, &file.lines[first..last], line - first))
+    }
+}
+
+/// The lines of the source file `name`, which is relative to the current
+/// directory or to `directory`
+fn read_source(name: &str, directory: &Path) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(name)
+        .or_else(|_| fs::read_to_string(directory.join(name)))
+        .map_err(|e| format!("Could not read {}: {}", name, e))?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functional_test_listing() {
+        let source = Source::from_listing("6502_functional_test.lst", include_str!("../6502_functional_test.lst")).unwrap();
+        let (name, lines, current) = source.around(0x0401, 2).unwrap();
+        assert_eq!(name, "6502_functional_test.lst");
+        assert_eq!(lines.len(), 5);
+        assert!(lines[current].ends_with("ldx #$ff"));
+        assert!(lines[current - 1].ends_with("start:  cld"));
+        // The .P02 line before start has no bytes
+        let (_, lines, current) = source.around(0x0400, 1).unwrap();
+        assert!(lines[current].contains("start:"));
+        assert!(source.around(0x0402, 1).is_none());
+    }
+
+    #[test]
+    fn debug_info_lines() {
+        let directory = std::env::temp_dir().join(format!("source_debug_info_lines_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("test.s"), "start:\n    lda #0\n    jmp start\n").unwrap();
+        let text = "version\tmajor=2,minor=0
+file\tid=0,name=\"test.s\",size=40,mtime=0x5E5F3A1B,mod=0
+line\tid=0,file=0,line=2,span=0
+line\tid=1,file=0,line=3,span=1
+line\tid=2,file=0,line=9,type=2,span=1
+line\tid=3,file=0,line=20,span=2
+seg\tid=0,name=\"CODE\",start=0x000400,size=0x0005,addrsize=absolute,type=ro
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=16,size=1
+";
+        let source = Source::from_debug_info(text, &directory).unwrap();
+        let line_zero = Source::from_debug_info(&text.replace("line=20", "line=0"), &directory);
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(line_zero.is_err());
+        let (name, lines, current) = source.around(0x0402, 5).unwrap();
+        assert_eq!(name, "test.s");
+        assert_eq!((lines.len(), lines[current].as_str()), (3, "    jmp start"));
+        assert_eq!(source.around(0x0401, 0).unwrap().1, &["    lda #0".to_string()]);
+        assert!(source.around(0x0405, 0).is_none());
+        // The source file has fewer lines than the debug info expects
+        assert!(source.around(0x0410, 8).is_none());
+    }
+}
@@ -0,0 +1,235 @@
+//! Symbol tables, so the debugger can show names instead of addresses.
+//! They come from o65 objects and from the files written by the cc65
+//! tools: ld65 map files (`-m`), VICE label files (`-Ln`) and debug info
+//! files (`--dbgfile`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::options::parse_number;
+
+/// How far after a symbol an address is still shown relative to it
+const MAX_OFFSET: u16 = 0x100;
+
+/// Names of addresses, for the debugger
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    addresses: HashMap<String, u16>,
+    /// The first name given to each address
+    names: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    /// Name `address`, a name already known moves to it
+    pub fn add(&mut self, name: &str, address: u16) {
+        if let Some(old) = self.addresses.insert(name.to_string(), address) {
+            if self.names.get(&old).map(String::as_str) == Some(name) {
+                self.names.remove(&old);
+                if let Some(other) = self.addresses.iter().find(|&(_, &a)| a == old).map(|(n, _)| n.clone()) {
+                    self.names.insert(old, other);
+                }
+            }
+        }
+        self.names.entry(address).or_insert_with(|| name.to_string());
+    }
+
+    /// The address of `name`
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The name of `address`, if it has one
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// The closest symbol at or before `address`, and how far it is
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        let (&symbol, name) = self.names.range(..=address).next_back()?;
+        let offset = address - symbol;
+        if offset >= MAX_OFFSET {
+            return None;
+        }
+        Some((name.as_str(), offset))
+    }
+
+    /// `address` with the symbol it is at, like `0x400 (start)`
+    pub fn label(&self, address: u16) -> String {
+        match self.name(address) {
+            Some(name) => format!("{:#x} ({})", address, name),
+            None => format!("{:#x}", address),
+        }
+    }
+
+    /// The symbol `address` is in, like `loop+3`
+    pub fn position(&self, address: u16) -> Option<String> {
+        self.locate(address).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+
+    /// An address given as a number or as a symbol
+    pub fn resolve(&self, text: &str) -> Result<u16, String> {
+        self.address(text).map(Ok).unwrap_or_else(|| parse_number(text).map_err(|_| format!("Unknown symbol {}", text)))
+    }
+
+    /// Add the symbols of a file, in any of the formats the cc65 tools write
+    pub fn load(&mut self, filename: &str) -> Result<(), String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("Could not read {}: {}", filename, e))?;
+        let result = if text.starts_with("version\t") {
+            self.parse_debug_info(&text)
+        } else if text.contains("Exports list by name:") {
+            self.parse_ld65_map(&text)
+        } else {
+            self.parse_vice_labels(&text)
+        };
+        result.map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    /// An ld65 map file: the exports, and the start of each segment
+    pub fn parse_ld65_map(&mut self, text: &str) -> Result<(), String> {
+        for line in section(text, "Segment list:").skip(2) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() < 2 {
+                return Err(format!("Invalid segment {}", line));
+            }
+            self.add(words[0], parse_hex(words[1])?);
+        }
+        // Two exports per line: name, value and flags
+        for line in section(text, "Exports list by name:") {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            for export in words.chunks(3) {
+                if export.len() < 2 {
+                    return Err(format!("Invalid export {}", line));
+                }
+                self.add(export[0], parse_hex(export[1])?);
+            }
+        }
+        Ok(())
+    }
+
+    /// A VICE label file, a line like `al C:0400 .start` per symbol
+    pub fn parse_vice_labels(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["al", address, name] => {
+                    let address = address.strip_prefix("C:").unwrap_or(address);
+                    self.add(name.strip_prefix('.').unwrap_or(name), parse_hex(address)?);
+                },
+                _ => return Err(format!("Invalid label {}", line)),
+            }
+        }
+        Ok(())
+    }
+
+    /// A ca65 debug info file, its labels are the `sym` lines of type `lab`
+    pub fn parse_debug_info(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines().filter_map(|line| line.strip_prefix("sym\t")) {
+            let attributes = debug_attributes(line);
+            if attributes.get("type") != Some(&"lab") {
+                continue;
+            }
+            match (attributes.get("name"), attributes.get("val")) {
+                (Some(name), Some(value)) => self.add(name.trim_matches('"'), parse_hex(value)?),
+                _ => return Err(format!("Invalid symbol {}", line)),
+            }
+        }
+        Ok(())
+    }
+
+    /// A line per symbol sorted by address, for the UI
+    pub fn describe(&self) -> Vec<String> {
+        let mut symbols: Vec<(&String, &u16)> = self.addresses.iter().collect();
+        symbols.sort_by_key(|&(name, address)| (*address, name.clone()));
+        symbols.iter().map(|(name, address)| format!("${:04X} {}", address, name)).collect()
+    }
+}
+
+/// The lines of a section of an ld65 map file, after its title and
+/// underline and up to the blank line ending it
+fn section<'a>(text: &'a str, title: &'a str) -> impl Iterator<Item = &'a str> {
+    text.lines()
+        .skip_while(move |line| line.trim() != title)
+        .skip(2)
+        .take_while(|line| !line.trim().is_empty())
+}
+
+/// The `key=value` attributes of a line of a debug info file
+pub(crate) fn debug_attributes(line: &str) -> HashMap<&str, &str> {
+    line.split(',')
+        .filter_map(|attribute| {
+            let mut parts = attribute.splitn(2, '=');
+            Some((parts.next()?, parts.next()?))
+        })
+        .collect()
+}
+
+/// A value written in hexadecimal, with or without `0x`. Addresses past
+/// 64KiB are in another bank of the 65C816, only their low 16 bits are kept.
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(digits, 16)
+        .map(|value| value as u16)
+        .map_err(|_| format!("Invalid address {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_addresses() {
+        let mut symbols = Symbols::default();
+        symbols.add("start", 0x0400);
+        symbols.add("reset", 0x0400);
+        symbols.add("loop", 0x0410);
+        assert_eq!(symbols.name(0x0400), Some("start"));
+        assert_eq!(symbols.address("reset"), Some(0x0400));
+        symbols.add("start", 0x0500);
+        assert_eq!((symbols.name(0x0400), symbols.name(0x0500)), (Some("reset"), Some("start")));
+        assert_eq!(symbols.describe(), vec!["$0400 reset", "$0410 loop", "$0500 start"]);
+        assert_eq!(symbols.position(0x0413), Some("loop+3".to_string()));
+        assert_eq!(symbols.position(0x0600), None);
+        assert_eq!(symbols.label(0x0500), "0x500 (start)");
+        assert_eq!(symbols.resolve("loop"), Ok(0x0410));
+        assert_eq!(symbols.resolve("$c000"), Ok(0xc000));
+        assert!(symbols.resolve("nowhere").is_err());
+    }
+
+    #[test]
+    fn ld65_map() {
+        let mut symbols = Symbols::default();
+        symbols.parse_ld65_map(include_str!("../6502_functional_test.map")).unwrap();
+        assert_eq!(symbols.address("CODE"), Some(0x0400));
+        assert_eq!(symbols.address("VECTORS"), Some(0xfffa));
+        symbols.parse_ld65_map("
+Exports list by name:
+---------------------
+__BSS_RUN__               000200 RLA    start                     000400 RLA
+reset                     00C000 RLA
+
+Exports list by value:
+").unwrap();
+        assert_eq!((symbols.address("start"), symbols.address("reset")), (Some(0x0400), Some(0xc000)));
+    }
+
+    #[test]
+    fn vice_labels() {
+        let mut symbols = Symbols::default();
+        symbols.parse_vice_labels("al 000400 .start\nal C:0410 .loop\n").unwrap();
+        assert_eq!((symbols.address("start"), symbols.address("loop")), (Some(0x0400), Some(0x0410)));
+        assert!(symbols.parse_vice_labels("start = $0400").is_err());
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut symbols = Symbols::default();
+        symbols.parse_debug_info("version\tmajor=2,minor=0
+sym\tid=0,name=\"start\",addrsize=absolute,scope=0,def=1,val=0x400,seg=0,type=lab
+sym\tid=1,name=\"count\",addrsize=zeropage,scope=0,def=2,val=0x10,type=equ
+").unwrap();
+        assert_eq!((symbols.address("start"), symbols.address("count")), (Some(0x0400), None));
+    }
+}
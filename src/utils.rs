@@ -1,6 +1,64 @@
 use cursive::views::*;
 use cursive::view::*;
 
+use cursive::theme::Effect;
+use cursive::utils::markup::StyledString;
+
+use crate::source::Source;
+use crate::symbols::Symbols;
+
+/// Bytes per line of the memory view
+const MEMORY_LINE: usize = 8;
+
+/// Lines of the source view before and after the current one
+const SOURCE_CONTEXT: usize = 8;
+
+/// The memory view: `data` from address `start`, 8 bytes per line. A line
+/// also ends before each address with a symbol, which gets a line of its own.
+pub fn memory_lines(start: u16, data: &[u8], symbols: &Symbols) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut count = 0;
+    for (offset, byte) in data.iter().enumerate() {
+        let address = start.wrapping_add(offset as u16);
+        let name = symbols.name(address);
+        if count == MEMORY_LINE || (name.is_some() && count > 0) {
+            lines.push(line);
+            line = String::new();
+            count = 0;
+        }
+        if let Some(name) = name {
+            lines.push(format!("{}:", name));
+        }
+        if count == 0 {
+            line = format!("{:04x}:", address);
+        }
+        line.push_str(&format!(" {:02x}", byte));
+        count += 1;
+    }
+    if count > 0 {
+        lines.push(line);
+    }
+    lines
+}
+
+/// The source view: the lines around the one `pc` comes from, that one
+/// highlighted
+pub fn source_text(source: &Source, pc: u16) -> StyledString {
+    let (name, lines, current) = match source.around(pc, SOURCE_CONTEXT) {
+        Some(around) => around,
+        None => return StyledString::plain(format!("(no source for {:#x})", pc)),
+    };
+    let mut text = StyledString::plain(format!("{}\n", name));
+    for (index, line) in lines.iter().enumerate() {
+        if index == current {
+            text.append_styled(format!("{}\n", line), Effect::Reverse);
+        } else {
+            text.append_plain(format!("{}\n", line));
+        }
+    }
+    text
+}
 
 pub fn layout() -> cursive::views::LinearLayout {
 
@@ -93,14 +151,41 @@ pub fn layout() -> cursive::views::LinearLayout {
                         TextView::new("").with_id("clock")
                     )
                 )
+                .child(
+                    DummyView.fixed_width(3)
+                )
+                .child(
+                    LinearLayout::vertical()
+                    .child(
+                        TextView::new("Bus")
+                    )
+                    .child(
+                        TextView::new("").with_id("bus")
+                    )
+                )
                 
-            ).title("Processor info").fixed_width(60))
+            ).title("Processor info").fixed_width(80))
+            .child(Dialog::around(
+                TextView::new("").with_id("source")
+            ).title("Source").fixed_width(80))
             .child(Dialog::around(
                 TextView::new("PROC INFO").with_id("info")
             ).title("Debug info").fixed_width(60).scrollable())
             .child(Dialog::around(
                 TextView::new("T").with_id("test")
             ).title("Current test").fixed_width(60).scrollable())
+            .child(Dialog::around(
+                TextView::new("").with_id("stack")
+            ).title("Stack").fixed_width(60).scrollable())
+            .child(Dialog::around(
+                TextView::new("").with_id("memory_map")
+            ).title("Memory map").fixed_width(60).scrollable())
+            .child(Dialog::around(
+                TextView::new("").with_id("banks")
+            ).title("Banks").fixed_width(60).scrollable())
+            .child(Dialog::around(
+                TextView::new("").with_id("symbols")
+            ).title("Symbols").fixed_width(60).scrollable())
             
         )
             
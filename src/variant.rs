@@ -0,0 +1,296 @@
+use std::fmt::Debug;
+
+use crate::addressing::AddressingMode as Mode;
+use crate::cycles::{CMOS_CYCLES, CYCLES, W65C816_CYCLES};
+use crate::instructions::{self, Instruction};
+
+/// The processors the computer can be built around, selected with `--cpu`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Variant {
+    /// The original NMOS 6502, undocumented opcodes included
+    #[default]
+    Nmos6502,
+    /// The CMOS 65C02 with the Rockwell and WDC additions
+    Cmos65c02,
+    /// The NES processor: an NMOS 6502 without decimal mode
+    Ricoh2a03,
+    /// The C64 processor: an NMOS 6502 with an I/O port at $00/$01
+    Mos6510,
+    /// The 16-bit 65C816, which starts in its 6502 emulation mode
+    W65c816,
+}
+
+impl Variant {
+    /// Parse the name given to `--cpu`
+    pub fn from_name(name: &str) -> Result<Variant, String> {
+        match name.to_lowercase().as_str() {
+            "6502" | "nmos" => Ok(Variant::Nmos6502),
+            "65c02" | "cmos" => Ok(Variant::Cmos65c02),
+            "2a03" | "nes" => Ok(Variant::Ricoh2a03),
+            "6510" | "c64" => Ok(Variant::Mos6510),
+            "65816" | "65c816" => Ok(Variant::W65c816),
+            _ => Err(format!("Unknown cpu {}", name)),
+        }
+    }
+
+    /// A processor of this variant, as it is after power on
+    pub fn cpu(self) -> Box<dyn Cpu> {
+        match self {
+            Variant::Nmos6502 => Box::new(Nmos6502),
+            Variant::Cmos65c02 => Box::new(Cmos65c02),
+            Variant::Ricoh2a03 => Box::new(Ricoh2a03),
+            Variant::Mos6510 => Box::new(Mos6510::default()),
+            Variant::W65c816 => Box::new(W65c816),
+        }
+    }
+}
+
+/// What happens to the opcodes the data sheet leaves out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IllegalOpcodes {
+    /// They run the undocumented instructions of the NMOS chips
+    Execute,
+    /// They do nothing, with the length of their addressing mode
+    Nop,
+}
+
+/// What sets a processor apart from the others. The computer runs every
+/// instruction the same way and asks its `Cpu` about the differences.
+pub trait Cpu: Debug {
+    fn name(&self) -> &'static str;
+
+    /// The instruction and addressing mode `opcode` has in the opcode table
+    /// of the chip
+    fn opcode_table(&self, opcode: u8) -> (Instruction, Mode) {
+        instructions::decode(opcode)
+    }
+
+    /// The instruction and addressing mode of `opcode`
+    fn decode(&self, opcode: u8) -> (Instruction, Mode) {
+        let (instruction, mode) = self.opcode_table(opcode);
+        if instruction.undocumented() && self.illegal_opcodes() == IllegalOpcodes::Nop {
+            return (Instruction::Nop, mode);
+        }
+        (instruction, mode)
+    }
+
+    /// Base number of cycles taken by `opcode`
+    fn cycles(&self, opcode: u8) -> u8 {
+        CYCLES[opcode as usize]
+    }
+
+    fn illegal_opcodes(&self) -> IllegalOpcodes {
+        IllegalOpcodes::Execute
+    }
+
+    /// Whether the D flag switches ADC, SBC and ARR to BCD
+    fn decimal(&self) -> bool {
+        true
+    }
+
+    /// Whether this is a CMOS chip: JMP ($xxFF) reads the right pointer,
+    /// decimal mode sets N and Z from the result and interrupts clear D
+    fn cmos(&self) -> bool {
+        false
+    }
+
+    /// Whether this is a 65C816, with its 16-bit registers, native mode and
+    /// 24-bit addresses. It runs on its own core.
+    fn native_mode(&self) -> bool {
+        false
+    }
+
+    /// The value of an on-chip register at `address`, if there is one
+    fn read_io(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    /// A write the on-chip registers may take, the memory below still gets it
+    fn write_io(&mut self, _address: u16, _value: u8) {}
+
+    /// The RESET line went through, on-chip registers go back to their initial state
+    fn reset(&mut self) {}
+}
+
+#[derive(Debug)]
+pub struct Nmos6502;
+
+impl Cpu for Nmos6502 {
+    fn name(&self) -> &'static str {
+        "6502"
+    }
+}
+
+#[derive(Debug)]
+pub struct Cmos65c02;
+
+impl Cpu for Cmos65c02 {
+    fn name(&self) -> &'static str {
+        "65C02"
+    }
+
+    fn opcode_table(&self, opcode: u8) -> (Instruction, Mode) {
+        instructions::decode_65c02(opcode)
+    }
+
+    fn cycles(&self, opcode: u8) -> u8 {
+        CMOS_CYCLES[opcode as usize]
+    }
+
+    fn illegal_opcodes(&self) -> IllegalOpcodes {
+        IllegalOpcodes::Nop
+    }
+
+    fn cmos(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct Ricoh2a03;
+
+impl Cpu for Ricoh2a03 {
+    fn name(&self) -> &'static str {
+        "2A03"
+    }
+
+    /// The decimal mode circuit is cut off: D can be set but ADC, SBC and
+    /// ARR stay binary
+    fn decimal(&self) -> bool {
+        false
+    }
+}
+
+/// Direction register and data register of the 6510 I/O port
+const PORT_DIRECTION: u16 = 0x0000;
+const PORT_DATA: u16 = 0x0001;
+
+#[derive(Debug, Default)]
+pub struct Mos6510 {
+    /// A 1 makes the pin an output
+    direction: u8,
+    /// Output latch
+    data: u8,
+}
+
+impl Cpu for Mos6510 {
+    fn name(&self) -> &'static str {
+        "6510"
+    }
+
+    /// Output pins read back the latch, nothing drives the input pins and
+    /// their pull-ups make them read as 1
+    fn read_io(&self, address: u16) -> Option<u8> {
+        match address {
+            PORT_DIRECTION => Some(self.direction),
+            PORT_DATA => Some(self.data & self.direction | !self.direction),
+            _ => None,
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            PORT_DIRECTION => self.direction = value,
+            PORT_DATA => self.data = value,
+            _ => {},
+        }
+    }
+
+    /// All the pins are inputs after a reset
+    fn reset(&mut self) {
+        self.direction = 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct W65c816;
+
+impl Cpu for W65c816 {
+    fn name(&self) -> &'static str {
+        "65C816"
+    }
+
+    fn opcode_table(&self, opcode: u8) -> (Instruction, Mode) {
+        instructions::decode_65c816(opcode)
+    }
+
+    fn cycles(&self, opcode: u8) -> u8 {
+        W65C816_CYCLES[opcode as usize]
+    }
+
+    fn illegal_opcodes(&self) -> IllegalOpcodes {
+        IllegalOpcodes::Nop
+    }
+
+    fn cmos(&self) -> bool {
+        true
+    }
+
+    fn native_mode(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(Variant::from_name("6502"), Ok(Variant::Nmos6502));
+        assert_eq!(Variant::from_name("65C02"), Ok(Variant::Cmos65c02));
+        assert_eq!(Variant::from_name("nes"), Ok(Variant::Ricoh2a03));
+        assert_eq!(Variant::from_name("c64"), Ok(Variant::Mos6510));
+        assert_eq!(Variant::from_name("65c816"), Ok(Variant::W65c816));
+        assert!(Variant::from_name("z80").is_err());
+        assert_eq!(Variant::default().cpu().name(), "6502");
+    }
+
+    #[test]
+    fn differences() {
+        let (nmos, cmos, nes) = (Variant::Nmos6502.cpu(), Variant::Cmos65c02.cpu(), Variant::Ricoh2a03.cpu());
+        assert_eq!((nmos.decimal(), nmos.cmos()), (true, false));
+        assert_eq!((cmos.decimal(), cmos.cmos()), (true, true));
+        assert_eq!((nes.decimal(), nes.cmos()), (false, false));
+        assert!(Variant::W65c816.cpu().native_mode());
+        // LAX zp
+        assert_eq!(nmos.decode(0xa7), (Instruction::Lax, Mode::ZeroPage));
+        assert_eq!(cmos.decode(0xa7).0, Instruction::Smb(2));
+        assert_eq!(cmos.decode(0xdc), (Instruction::Nop, Mode::Absolute));
+    }
+
+    /// An NMOS chip whose undocumented opcodes do nothing
+    #[derive(Debug)]
+    struct Quiet;
+
+    impl Cpu for Quiet {
+        fn name(&self) -> &'static str {
+            "quiet"
+        }
+
+        fn illegal_opcodes(&self) -> IllegalOpcodes {
+            IllegalOpcodes::Nop
+        }
+    }
+
+    #[test]
+    fn undocumented_opcodes_as_nops() {
+        assert_eq!(Quiet.decode(0xa7), (Instruction::Nop, Mode::ZeroPage));
+        assert_eq!(Quiet.decode(0xa5), (Instruction::Lda, Mode::ZeroPage));
+        for opcode in 0..=255 {
+            assert!(!Variant::Cmos65c02.cpu().decode(opcode).0.undocumented());
+        }
+    }
+
+    #[test]
+    fn mos6510_port() {
+        let mut cpu = Mos6510::default();
+        // Bits 0-2 are outputs, the inputs read as 1
+        cpu.write_io(PORT_DIRECTION, 0x07);
+        cpu.write_io(PORT_DATA, 0x05);
+        assert_eq!(cpu.read_io(PORT_DATA), Some(0xfd));
+        assert_eq!(cpu.read_io(0x0002), None);
+        cpu.reset();
+        assert_eq!(cpu.read_io(PORT_DATA), Some(0xff));
+    }
+}